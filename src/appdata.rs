@@ -1,12 +1,24 @@
-use log::info;
-use tokio::sync::RwLock;
-use uqoin_core::pool::Pool;
+use log::{info, warn, error};
+use tokio::io::{Error, ErrorKind};
+use tokio::sync::{RwLock, Mutex, Notify, watch};
+use tokio::time::{sleep, Duration};
 use uqoin_core::state::State;
 use uqoin_core::schema::Schema;
 use uqoin_core::blockchain::Blockchain;
 
 use crate::utils::*;
-use crate::config::Config;
+use crate::pool::Pool;
+use crate::peers::{Peers, resolve_nodes};
+use crate::snapshots::Snapshots;
+use crate::history::History;
+use crate::ratelimit::RateLimiter;
+use crate::metrics::Metrics;
+use crate::mining::Mining;
+use crate::events::{Event, Envelope, Events};
+use crate::auth::SignatureCache;
+use crate::config::{Config, VerifyMode};
+use crate::chain::{load_state, read_state, dump_state, replay, 
+                   states_equal};
 
 
 pub struct AppData {
@@ -15,22 +27,53 @@ pub struct AppData {
     pub pool: RwLock<Pool>,
     pub state: RwLock<State>,
     pub blockchain: RwLock<Blockchain>,
-    pub nodes: RwLock<Vec<String>>,
+    pub nodes: RwLock<Peers>,
+    pub snapshots: Snapshots,
+    pub history: RwLock<History>,
+    pub rate_limiter: RateLimiter,
+    pub metrics: Metrics,
+    pub mining: Mining,
+    pub events: Events,
     pub is_syncing: RwLock<bool>,
+    pub syncing_override: RwLock<Option<bool>>,
+    pub sync_target: RwLock<Option<String>>,
+    pub sync_notify: Notify,
+    pub sync_lock: Mutex<()>,
+    pub shutdown: watch::Sender<bool>,
+    pub admin_signatures: SignatureCache,
 }
 
 
 impl AppData {
     pub async fn new(config: Config) -> TokioResult<Self> {
         let schema = Schema::new();
-        let pool = RwLock::new(Pool::new());
+        let events = Events::default();
+        let mut pool = Pool::new(config.pool_groups_max, 
+                                 config.pool_sender_groups_max);
+        pool.set_events(events.clone());
+        let pool = RwLock::new(pool);
         let state = RwLock::new(State::new());
         let blockchain = RwLock::new(Blockchain::new(&config.data_path).await?);
-        let nodes = RwLock::new(config.nodes.clone());
+        let nodes = RwLock::new(Peers::new(config.nodes.clone(), 
+                                           config.get_ban_policy()));
+        let snapshots = config.get_snapshots();
+        let history = RwLock::new(History::new(&config.data_path).await?);
+        let rate_limiter = config.get_rate_limiter();
+        let metrics = Metrics::default();
+        let mining = Mining::new(config.mining_threads);
         let is_syncing = RwLock::new(true);
+        let syncing_override = RwLock::new(None);
+        let sync_target = RwLock::new(None);
+        let sync_notify = Notify::new();
+        let sync_lock = Mutex::new(());
+        let shutdown = watch::Sender::new(false);
+        let admin_signatures = SignatureCache::default();
 
         let mut instance = Self {
-            config, schema, pool, state, blockchain, nodes, is_syncing
+            config, schema, pool, state, blockchain, nodes, snapshots, history,
+            rate_limiter, metrics, mining, events, is_syncing, 
+            syncing_override, sync_target, sync_notify, sync_lock, shutdown, 
+            admin_signatures,
         };
         instance.initialize().await?;
         info!("AppData is ready");
@@ -39,26 +82,139 @@ impl AppData {
     }
 
     async fn initialize(&mut self) -> TokioResult<()> {
-        // Try to load state
-        if let Ok(state) = State::load(&self.config.get_state_path()).await {
-            *self.state.write().await = state;
-            info!("State loaded from file");
+        // Nodes saved in runtime replace the configured ones, so the removed
+        // nodes do not come back
+        let nodes_path = self.config.get_nodes_path();
+        if let Ok(nodes) = Peers::load_list(&nodes_path).await {
+            *self.nodes.write().await = Peers::new(
+                nodes, self.config.get_ban_policy()
+            );
+            info!("Nodes loaded from file");
+        }
+
+        // Load state or evolve it through the blockchain verifying the blocks
+        let state = if self.config.verify_on_start == VerifyMode::Off {
+            load_state(&self.config.get_state_path(),
+                       &*self.blockchain.read().await, &self.schema,
+                       &self.snapshots).await?
         } else {
-            // Evolve state through the blockchain
-            info!("Could not load state from file");
-            info!("Evolving state through the blockchain");
-            let blockchain = self.blockchain.read().await;
-            let mut state = self.state.write().await;
-            let block_count = blockchain.get_block_count().await?;
-            for bix in 1..=block_count {
-                let block = blockchain.get_block(bix).await?;
-                let transactions = 
-                    blockchain.get_transactions_of_block(&block).await?;
-                state.roll_up(bix, &block, &transactions, &self.schema);
-            }
-            state.dump(&self.config.get_state_path()).await?;
-            info!("State is ready")
+            self.verify_state().await?
+        };
+        self.snapshots.save(&state).await?;
+
+        // Bring the wallet history in line with the blockchain
+        self.history.write().await.load(&*self.blockchain.read().await,
+                                        &self.schema, &self.snapshots).await?;
+
+        // Restore pending groups saved before restart
+        let mut pool = self.pool.write().await;
+        if let Ok(count) = pool.load(&self.config.get_pool_path(), &state, 
+                                     &self.schema).await {
+            pool.prune(self.config.pool_group_age_max);
+            info!("Pool loaded from file, {} of {} groups are restored", 
+                  pool.len(), count);
         }
+        drop(pool);
+
+        *self.state.write().await = state;
         Ok(())
     }
+
+    /// Replay the blockchain from scratch validating every block and compare
+    /// the result with the state file. Depending on `VERIFY_ON_START` it
+    /// fails or truncates the blockchain at the first invalid block and
+    /// replaces the mismatching state file.
+    async fn verify_state(&self) -> TokioResult<State> {
+        info!("Verifying the blockchain");
+        let mode = self.config.verify_on_start;
+        let blockchain = self.blockchain.read().await;
+
+        // Replay with validation
+        let mut state = State::new();
+        if let Some(invalid) = replay(&mut state, &blockchain, &self.schema,
+                                      true).await? {
+            error!("Block {} is invalid: {}", invalid.bix, invalid.error);
+            if mode == VerifyMode::Strict {
+                return Err(Error::new(ErrorKind::InvalidData, format!(
+                    "Block {} is invalid: {}", invalid.bix, invalid.error
+                )));
+            }
+            warn!("Truncating the blockchain to bix = {}", invalid.bix - 1);
+            blockchain.truncate(invalid.bix - 1).await?;
+            self.snapshots.discard_after(invalid.bix - 1).await?;
+        }
+
+        // Compare with the state file
+        let path = self.config.get_state_path();
+        match read_state(&path).await {
+            Ok(state_loaded) if states_equal(&state_loaded, &state) => {
+                info!("State file matches the blockchain");
+            },
+            Ok(_) => {
+                error!("State file does not match the blockchain");
+                if mode == VerifyMode::Strict {
+                    return Err(Error::new(ErrorKind::InvalidData, 
+                        "State file does not match the blockchain"));
+                }
+                warn!("Replacing the state file");
+                dump_state(&path, &state).await?;
+            },
+            Err(_) => {
+                info!("Could not load state from file");
+                dump_state(&path, &state).await?;
+            },
+        }
+
+        info!("Blockchain is verified up to bix = {}", 
+              state.get_last_block_info().bix);
+        Ok(state)
+    }
+
+    /// Set the syncing flag emitting `sync_status` if it changes.
+    pub async fn set_syncing(&self, value: bool) {
+        let mut is_syncing = self.is_syncing.write().await;
+        if *is_syncing != value {
+            *is_syncing = value;
+            self.events.emit(Envelope::global(Event::SyncStatus { 
+                is_syncing: value 
+            }));
+        }
+    }
+
+    /// Request the sync task to sync with the node immediately.
+    pub async fn request_sync(&self, node: String) {
+        *self.sync_target.write().await = Some(node);
+        self.sync_notify.notify_one();
+    }
+
+    /// Exempt the known nodes from the rate limit resolving their IPs. It must
+    /// be called whenever the list of nodes changes.
+    pub async fn refresh_exempt(&self) {
+        let nodes = self.nodes.read().await.list().to_vec();
+        self.rate_limiter.set_exempt(resolve_nodes(&nodes).await);
+    }
+
+    /// Signal the background tasks to stop after their current steps.
+    pub fn request_shutdown(&self) {
+        self.shutdown.send_replace(true);
+    }
+
+    /// Check if the shutdown is signalled.
+    pub fn is_shutting_down(&self) -> bool {
+        *self.shutdown.borrow()
+    }
+
+    /// Wait until the shutdown is signalled.
+    pub async fn shutdown_signalled(&self) {
+        let _ = self.shutdown.subscribe().wait_for(|value| *value).await;
+    }
+
+    /// Sleep for `millis` milliseconds. It returns `true` if the shutdown is
+    /// signalled meanwhile.
+    pub async fn sleep_or_shutdown(&self, millis: u64) -> bool {
+        tokio::select! {
+            _ = sleep(Duration::from_millis(millis)) => false,
+            _ = self.shutdown_signalled() => true,
+        }
+    }
 }
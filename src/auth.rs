@@ -0,0 +1,184 @@
+use std::sync::Mutex;
+use std::future::{Ready, ready};
+use std::collections::HashMap;
+
+use sha3::{Digest, Sha3_256};
+use actix_web::{FromRequest, HttpRequest};
+use actix_web::dev::Payload;
+use actix_web::http::header::{AUTHORIZATION, CONTENT_LENGTH, 
+                               TRANSFER_ENCODING};
+
+use crate::utils::*;
+use crate::error::{JsonError, ErrorCode};
+
+
+/// Header with the request time (in milliseconds since epoch) for HMAC.
+const TIMESTAMP_HEADER: &str = "X-Admin-Timestamp";
+
+/// Header with the HMAC signature of the request.
+const SIGNATURE_HEADER: &str = "X-Admin-Signature";
+
+/// Maximum difference between the request time and the node time (in
+/// milliseconds) for HMAC.
+const SIGNATURE_WINDOW: u64 = 300000;
+
+
+/// Extractor that passes only the requests of the node operator. The request
+/// must have the header `Authorization: Bearer <ADMIN_TOKEN>` or be signed
+/// with `ADMIN_HMAC_SECRET`: the header `X-Admin-Timestamp` contains the
+/// current time in milliseconds and `X-Admin-Signature` contains hex of
+/// HMAC-SHA3-256 of `<timestamp>\n<method>\n<path and query>`. Signed
+/// requests must have no body and every signature is accepted only once. If
+/// neither the token nor the secret is configured, all requests are rejected.
+/// The field is the authentication method (`token` or `hmac`).
+pub struct Admin(pub &'static str);
+
+
+impl FromRequest for Admin {
+    type Error = JsonError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(authenticate(req).map(Admin))
+    }
+}
+
+
+/// Signatures accepted recently (to reject replayed requests).
+#[derive(Debug, Default)]
+pub struct SignatureCache(Mutex<HashMap<String, u64>>);
+
+
+impl SignatureCache {
+    /// Remember the signature valid until `expires`. It returns `false` if
+    /// the signature has already been used.
+    fn insert(&self, signature: &str, expires: u64) -> bool {
+        let mut signatures = self.0.lock().unwrap();
+        let now = timestamp();
+        signatures.retain(|_, until| *until > now);
+        signatures.insert(signature.to_string(), expires).is_none()
+    }
+}
+
+
+fn authenticate(req: &HttpRequest) -> Result<&'static str, JsonError> {
+    let appdata = req.app_data::<WebAppData>()
+        .ok_or(JsonError::new(ErrorCode::Unauthorized))?;
+
+    if req.headers().contains_key(AUTHORIZATION) {
+        check_token(req, appdata).map(|_| "token")
+    } else {
+        check_signature(req, appdata).map(|_| "hmac")
+    }
+}
+
+
+fn check_token(req: &HttpRequest, 
+               appdata: &WebAppData) -> Result<(), JsonError> {
+    let expected = appdata.config.admin_token.as_ref()
+        .ok_or(JsonError::new(ErrorCode::Unauthorized))?;
+
+    let token = req.headers().get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .ok_or(JsonError::new(ErrorCode::Unauthorized))?;
+
+    if constant_time_eq(token.as_bytes(), expected.as_bytes()) {
+        Ok(())
+    } else {
+        Err(JsonError::new(ErrorCode::Unauthorized))
+    }
+}
+
+
+fn check_signature(req: &HttpRequest, 
+                   appdata: &WebAppData) -> Result<(), JsonError> {
+    let secret = appdata.config.admin_hmac_secret.as_ref()
+        .ok_or(JsonError::new(ErrorCode::Unauthorized))?;
+
+    let header = |name| req.headers().get(name)
+        .and_then(|value| value.to_str().ok())
+        .ok_or(JsonError::new(ErrorCode::Unauthorized));
+    let time: u64 = header(TIMESTAMP_HEADER)?.parse()
+        .map_err(|_| JsonError::new(ErrorCode::Unauthorized))?;
+    let signature = header(SIGNATURE_HEADER)?.to_lowercase();
+
+    // The body is not signed, so it is not allowed (chunked bodies have no
+    // length)
+    let has_body = req.headers().contains_key(TRANSFER_ENCODING) || 
+        req.headers().get(CONTENT_LENGTH).is_some_and(|value| value != "0");
+    let now = timestamp();
+    let is_valid = !has_body && now.abs_diff(time) <= SIGNATURE_WINDOW && 
+        constant_time_eq(signature.as_bytes(), 
+                         sign_request(secret, time, req.method().as_str(), 
+                                      &path_and_query(req)).as_bytes()) &&
+        appdata.admin_signatures.insert(&signature, time + SIGNATURE_WINDOW);
+
+    if is_valid {
+        Ok(())
+    } else {
+        Err(JsonError::new(ErrorCode::Unauthorized))
+    }
+}
+
+
+fn path_and_query(req: &HttpRequest) -> String {
+    if req.query_string().is_empty() {
+        req.path().to_string()
+    } else {
+        format!("{}?{}", req.path(), req.query_string())
+    }
+}
+
+
+/// Signature of the request as hex of HMAC-SHA3-256.
+fn sign_request(secret: &str, time: u64, method: &str, path: &str) -> String {
+    let message = format!("{}\n{}\n{}", time, method, path);
+    hmac_sha3_256(secret.as_bytes(), message.as_bytes()).iter()
+        .map(|byte| format!("{:02x}", byte)).collect()
+}
+
+
+fn hmac_sha3_256(key: &[u8], message: &[u8]) -> [u8; 32] {
+    const BLOCK_SIZE: usize = 136;
+
+    let mut block = [0u8; BLOCK_SIZE];
+    if key.len() > BLOCK_SIZE {
+        block[..32].copy_from_slice(&Sha3_256::digest(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+
+    let pad = |byte: u8| block.iter().map(|b| b ^ byte).collect::<Vec<u8>>();
+    let inner = Sha3_256::new().chain_update(pad(0x36)).chain_update(message)
+                               .finalize();
+    Sha3_256::new().chain_update(pad(0x5c)).chain_update(inner)
+                   .finalize().into()
+}
+
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && 
+        a.iter().zip(b.iter()).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hmac_sha3_256() {
+        let key: Vec<u8> = (0..32).collect();
+        let hex: String = hmac_sha3_256(
+            &key, b"Sample message for keylen<blocklen"
+        ).iter().map(|byte| format!("{:02x}", byte)).collect();
+        assert_eq!(hex, "4fe8e202c4f058e8dddc23d8c34e467343e23555e24fc2f0\
+                         25d598f558f67205");
+
+        let hex: String = hmac_sha3_256(&[b'k'; 200], b"msg").iter()
+            .map(|byte| format!("{:02x}", byte)).collect();
+        assert_eq!(hex, "4beeb04d637f2720b7472094d5aec0e1a6d78e52b0e4efc9\
+                         870196be1593f4f2");
+    }
+}
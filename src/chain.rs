@@ -0,0 +1,315 @@
+use log::{info, warn};
+use serde_json::Value as JsonValue;
+use uqoin_core::utils::U256;
+use uqoin_core::state::State;
+use uqoin_core::schema::Schema;
+use uqoin_core::coin::coin_order;
+use uqoin_core::block::{Block, BlockData, COMPLEXITY};
+use uqoin_core::blockchain::Blockchain;
+use uqoin_core::transaction::{Transaction, Type};
+
+use crate::utils::*;
+use crate::snapshots::Snapshots;
+
+
+/// Number of blocks read from the blockchain at once on replay.
+const REPLAY_CHUNK_SIZE: u64 = 1000;
+
+
+/// Block that did not pass the validation.
+#[derive(Debug, Clone)]
+pub struct InvalidBlock {
+    /// Number of the block.
+    pub bix: u64,
+
+    /// Validation error.
+    pub error: String,
+}
+
+
+/// Coin moved to or from the wallet by the transaction `tix`.
+#[derive(Debug, Clone)]
+pub struct CoinMove {
+    pub wallet: U256,
+    pub coin: U256,
+    pub order: u64,
+    pub tix: u64,
+    pub is_incoming: bool,
+}
+
+
+/// Load the state from the file and bring it in line with the blockchain.
+/// If the file cannot be loaded or its last block does not belong to the
+/// blockchain (e.g. after a crash during a reorg), the state is restored from
+/// the nearest snapshot or from scratch. Then it is rolled forward up to the
+/// last block and dumped to the file.
+pub async fn load_state(path: &str, blockchain: &Blockchain, schema: &Schema,
+                        snapshots: &Snapshots) -> TokioResult<State> {
+    let block_count = blockchain.get_block_count().await?;
+
+    let loaded = match read_state(path).await {
+        Ok(state) if is_state_on_chain(&state, blockchain).await? => {
+            info!("State loaded from file, bix = {}",
+                  state.get_last_block_info().bix);
+            Some(state)
+        },
+        Ok(state) => {
+            warn!("State file with bix = {} does not match the blockchain",
+                  state.get_last_block_info().bix);
+            None
+        },
+        Err(_) => {
+            info!("Could not load state from file");
+            None
+        },
+    };
+    let is_loaded = loaded.is_some();
+
+    let mut state = if let Some(state) = loaded {
+        state
+    } else if let Some(state) = snapshots.load_nearest(block_count,
+                                                       blockchain).await? {
+        info!("Snapshot loaded, bix = {}", state.get_last_block_info().bix);
+        state
+    } else {
+        State::new()
+    };
+
+    let bix = state.get_last_block_info().bix;
+    if bix < block_count {
+        info!("Evolving state through the blockchain from bix = {}", bix);
+        replay(&mut state, blockchain, schema, false).await?;
+    }
+
+    if !is_loaded || bix < block_count {
+        dump_state(path, &state).await?;
+        info!("State is ready");
+    }
+
+    Ok(state)
+}
+
+
+/// Build the state at the block `bix` starting from the nearest snapshot.
+pub async fn build_state_at(bix: u64, blockchain: &Blockchain, schema: &Schema,
+                            snapshots: &Snapshots) -> TokioResult<State> {
+    let mut state = snapshots.load_nearest(bix, blockchain).await?
+                             .unwrap_or_else(State::new);
+    replay_until(&mut state, blockchain, schema, bix, false).await?;
+    Ok(state)
+}
+
+
+/// Read the state from the file.
+pub async fn read_state(path: &str) -> TokioResult<State> {
+    let content = tokio::fs::read(path).await?;
+    Ok(serde_json::from_slice(&content)?)
+}
+
+
+/// Write the state to the file atomically.
+pub async fn dump_state(path: &str, state: &State) -> TokioResult<()> {
+    let content = serde_json::to_vec(state)?;
+    write_atomic(path, &content).await
+}
+
+
+/// Check if the last block of the state belongs to the blockchain.
+pub async fn is_state_on_chain(state: &State, 
+                               blockchain: &Blockchain) -> TokioResult<bool> {
+    let block_info = state.get_last_block_info();
+    if block_info.bix == 0 {
+        Ok(true)
+    } else if block_info.bix > blockchain.get_block_count().await? {
+        Ok(false)
+    } else {
+        let hash = blockchain.get_block_info(block_info.bix).await?.hash;
+        Ok(block_info.hash == hash)
+    }
+}
+
+
+/// Roll up the state with the blocks following its last block up to the end
+/// of the blockchain. If `validate` is set, every block is fully validated
+/// (hash chain, complexity, transactions and signatures) before it is applied,
+/// and the replay stops at the first invalid block that is returned.
+pub async fn replay(state: &mut State, blockchain: &Blockchain, schema: &Schema,
+                    validate: bool) -> TokioResult<Option<InvalidBlock>> {
+    let block_count = blockchain.get_block_count().await?;
+    replay_until(state, blockchain, schema, block_count, validate).await
+}
+
+
+/// Same as `replay` but up to the block `bix_to` inclusively.
+pub async fn replay_until(state: &mut State, blockchain: &Blockchain, 
+                          schema: &Schema, bix_to: u64, 
+                          validate: bool) -> 
+                          TokioResult<Option<InvalidBlock>> {
+    let mut bix = state.get_last_block_info().bix + 1;
+
+    while bix <= bix_to {
+        let count = std::cmp::min(REPLAY_CHUNK_SIZE, bix_to + 1 - bix);
+        let blocks = blockchain.get_block_data_many(bix, count).await?;
+
+        for block_data in blocks.iter() {
+            if validate && let Err(error) = validate_block(block_data, state,
+                                                           schema) {
+                return Ok(Some(InvalidBlock { bix: block_data.bix, error }));
+            }
+            state.roll_up(block_data.bix, &block_data.block,
+                          &block_data.transactions, schema);
+        }
+
+        bix += count;
+        info!("Replayed up to bix = {} of {}", bix - 1, bix_to);
+    }
+
+    Ok(None)
+}
+
+
+/// Collect groups of the transactions in the blocks from `bix_from` to `bix_to`
+/// inclusively. The groups are split by the transaction types, so no state is
+/// needed, and the extensions of splits and merges are skipped.
+pub async fn collect_groups(blockchain: &Blockchain, bix_from: u64, 
+                            bix_to: u64) -> TokioResult<Vec<Vec<Transaction>>> {
+    let mut groups = Vec::new();
+    let mut bix = bix_from;
+
+    while bix <= bix_to {
+        let count = std::cmp::min(REPLAY_CHUNK_SIZE, bix_to + 1 - bix);
+        let blocks = blockchain.get_block_data_many(bix, count).await?;
+        for block_data in blocks.iter() {
+            groups.extend(split_groups(&block_data.transactions));
+        }
+        bix += count;
+    }
+
+    Ok(groups)
+}
+
+
+/// Split the transactions of a block into groups (see `collect_groups`).
+pub fn split_groups(transactions: &[Transaction]) -> Vec<Vec<Transaction>> {
+    let mut groups = Vec::new();
+    let mut ix = 0;
+
+    while ix < transactions.len() {
+        let (size, ext_size) = match transactions[ix].get_type() {
+            Type::Split => (1, 3),
+            Type::Merge => (3, 1),
+            Type::Transfer => (1, 0),
+            Type::Fee => break,
+        };
+        let mut end = std::cmp::min(ix + size, transactions.len());
+        if end < transactions.len() && 
+                transactions[end].get_type() == Type::Fee {
+            end += 1;
+        }
+        groups.push(transactions[ix..end].to_vec());
+        ix = end + ext_size;
+    }
+
+    groups
+}
+
+
+/// Fully validate the block as the next one for the state.
+pub fn validate_block(block_data: &BlockData, state: &State,
+                      schema: &Schema) -> Result<(), String> {
+    let senders = Transaction::calc_senders(&block_data.transactions, state,
+                                            schema);
+    block_data.block.validate(
+        &block_data.transactions, state.get_last_block_info(), COMPLEXITY,
+        state, &senders
+    ).map_err(|err| err.to_string())
+}
+
+
+/// Coin moves of the wallets made by the block. `state` and `senders` must
+/// correspond to the moment before the block. The coin of every transaction
+/// goes from the sender to the receiver (the validator for fees, splits and
+/// merges), new coins are only received.
+pub fn coin_moves(block: &Block, transactions: &[Transaction], 
+                  senders: &[U256], state: &State) -> Vec<CoinMove> {
+    let mut moves = Vec::new();
+
+    for (ix, (tr, sender)) in transactions.iter().zip(senders.iter())
+                                          .enumerate() {
+        let receiver = if tr.get_type() == Type::Transfer {
+            &tr.addr
+        } else {
+            &block.validator
+        };
+
+        let coin_info = state.get_coin_info(&tr.coin);
+        if coin_info.is_some() && receiver == sender {
+            continue;
+        }
+        let order = coin_info.map(|info| info.order)
+            .unwrap_or_else(|| coin_order(&tr.coin, sender));
+        let tix = block.offset + ix as u64 + 1;
+
+        if coin_info.is_some() {
+            moves.push(CoinMove { 
+                wallet: sender.clone(), coin: tr.coin.clone(), order, tix, 
+                is_incoming: false,
+            });
+        }
+        moves.push(CoinMove { 
+            wallet: receiver.clone(), coin: tr.coin.clone(), order, tix, 
+            is_incoming: true,
+        });
+    }
+
+    moves
+}
+
+
+/// Check if the states are equal. Sets of coins are compared regardless of
+/// their order.
+pub fn states_equal(state1: &State, state2: &State) -> bool {
+    let normalize = |state| serde_json::to_value(state).map(normalize_value);
+    match (normalize(state1), normalize(state2)) {
+        (Ok(value1), Ok(value2)) => value1 == value2,
+        _ => false,
+    }
+}
+
+
+fn normalize_value(value: JsonValue) -> JsonValue {
+    match value {
+        JsonValue::Array(items) => {
+            let mut items: Vec<JsonValue> = items.into_iter()
+                .map(normalize_value).collect();
+            items.sort_by_cached_key(|item| item.to_string());
+            JsonValue::Array(items)
+        },
+        JsonValue::Object(map) => JsonValue::Object(
+            map.into_iter().map(|(k, v)| (k, normalize_value(v))).collect()
+        ),
+        value => value,
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tr(addr: u64) -> Transaction {
+        Transaction::new(U256::from(100), U256::from(addr), U256::from(0), 
+                         U256::from(0))
+    }
+
+    #[test]
+    fn test_split_groups() {
+        // Transfer with fee, split with its extension, merge without fee
+        let transactions = [tr(10), tr(0), tr(1), tr(0), tr(5), tr(5), tr(5),
+                            tr(2), tr(2), tr(2), tr(5), tr(10)];
+        let groups = split_groups(&transactions);
+        let sizes: Vec<usize> = groups.iter().map(|g| g.len()).collect();
+        assert_eq!(sizes, vec![2, 2, 3, 1]);
+        assert_eq!(groups[3][0].addr, U256::from(10));
+    }
+}
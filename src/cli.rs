@@ -0,0 +1,59 @@
+use clap::{Parser, Subcommand};
+
+
+/// Node for Uqoin ecosystem.
+#[derive(Parser)]
+#[command(version)]
+pub struct Args {
+    /// Path to the config file (TOML or JSON). Environment variables override
+    /// the values from the file.
+    #[arg(long, env = "CONFIG_PATH", global = true)]
+    pub config: Option<String>,
+
+    /// Print the effective config and exit.
+    #[arg(long, global = true)]
+    pub print_config: bool,
+
+    /// Command to execute (`run` if not specified).
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Run the node.
+    Run,
+
+    /// Evolve the state through the blockchain ignoring the state file.
+    RebuildState,
+
+    /// Replay the blockchain validating every block.
+    Verify,
+
+    /// Export blocks into a file of JSON lines.
+    Export {
+        /// First block to export.
+        #[arg(long, default_value_t = 1)]
+        from: u64,
+
+        /// Last block to export (the last block of the blockchain if not
+        /// specified).
+        #[arg(long)]
+        to: Option<u64>,
+
+        /// Output file.
+        #[arg(long)]
+        output: String,
+    },
+
+    /// Import blocks from a file of JSON lines (the node must be stopped).
+    Import {
+        /// Input file.
+        #[arg(long)]
+        input: String,
+    },
+
+    /// Generate a new private key.
+    Keygen,
+}
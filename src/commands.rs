@@ -0,0 +1,156 @@
+use std::io::{Error, ErrorKind, BufRead, BufReader, BufWriter, Write};
+
+use uqoin_core::state::State;
+use uqoin_core::schema::Schema;
+use uqoin_core::block::BlockData;
+use uqoin_core::blockchain::Blockchain;
+
+use crate::utils::*;
+use crate::config::Config;
+use crate::chain::{load_state, dump_state, replay, validate_block};
+
+
+/// Number of blocks read from the blockchain at once on export.
+const EXPORT_CHUNK_SIZE: u64 = 1000;
+
+
+/// Evolve the state through the blockchain from scratch ignoring the state
+/// file and dump the result.
+pub async fn rebuild_state(config: &Config) -> TokioResult<()> {
+    let schema = Schema::new();
+    let blockchain = Blockchain::new(&config.data_path).await?;
+    let mut state = State::new();
+    replay(&mut state, &blockchain, &schema, false).await?;
+    dump_state(&config.get_state_path(), &state).await?;
+    println!("State is rebuilt up to bix = {}", 
+             state.get_last_block_info().bix);
+    Ok(())
+}
+
+
+/// Replay the blockchain from scratch validating every block.
+pub async fn verify(config: &Config) -> TokioResult<()> {
+    let schema = Schema::new();
+    let blockchain = Blockchain::new(&config.data_path).await?;
+    let mut state = State::new();
+    if let Some(invalid) = replay(&mut state, &blockchain, &schema, 
+                                  true).await? {
+        Err(Error::new(ErrorKind::InvalidData, format!(
+            "Block {} is invalid: {}", invalid.bix, invalid.error
+        )))
+    } else {
+        println!("All {} blocks are valid", state.get_last_block_info().bix);
+        Ok(())
+    }
+}
+
+
+/// Export blocks from `bix_from` to `bix_to` (the last block if not 
+/// specified) into the file as JSON lines of block data.
+pub async fn export(config: &Config, bix_from: u64, bix_to: Option<u64>, 
+                    output: &str) -> TokioResult<()> {
+    let blockchain = Blockchain::new(&config.data_path).await?;
+    let block_count = blockchain.get_block_count().await?;
+    let bix_to = bix_to.unwrap_or(block_count);
+
+    if bix_from == 0 || bix_from > bix_to || bix_to > block_count {
+        return Err(Error::new(ErrorKind::InvalidInput, format!(
+            "Invalid block range {}..={}, there are {} blocks", 
+            bix_from, bix_to, block_count
+        )));
+    }
+
+    let mut writer = BufWriter::new(std::fs::File::create(output)?);
+    let mut bix = bix_from;
+    while bix <= bix_to {
+        let count = std::cmp::min(EXPORT_CHUNK_SIZE, bix_to + 1 - bix);
+        for block_data in blockchain.get_block_data_many(bix, count).await? {
+            serde_json::to_writer(&mut writer, &block_data)?;
+            writer.write_all(b"\n")?;
+        }
+        bix += count;
+    }
+    writer.flush()?;
+
+    println!("Exported {} blocks to {}", bix_to + 1 - bix_from, output);
+    Ok(())
+}
+
+
+/// Import blocks from the file of JSON lines of block data. Blocks that are
+/// already in the blockchain are skipped if they match, new blocks are 
+/// validated and appended. The node must be stopped during the import.
+pub async fn import(config: &Config, input: &str) -> TokioResult<()> {
+    let schema = Schema::new();
+    let blockchain = Blockchain::new(&config.data_path).await?;
+    let snapshots = config.get_snapshots();
+    let mut state = load_state(&config.get_state_path(), &blockchain, 
+                               &schema, &snapshots).await?;
+
+    let reader = BufReader::new(std::fs::File::open(input)?);
+    let result = import_blocks(reader, &blockchain, &mut state, &schema).await;
+
+    // Dump the state anyway since the imported blocks are already pushed
+    dump_state(&config.get_state_path(), &state).await?;
+    snapshots.save(&state).await?;
+
+    let count = result?;
+    println!("Imported {} blocks, last bix = {}", count, 
+             state.get_last_block_info().bix);
+    Ok(())
+}
+
+
+/// Generate a new private key and print it with the wallet address.
+pub fn keygen() {
+    let (key, public) = Schema::new().gen_pair(&mut rand::rng());
+    println!("PRIVATE_KEY={}", key.to_hex());
+    println!("WALLET={}", public.to_hex());
+}
+
+
+async fn import_blocks<R: BufRead>(reader: R, blockchain: &Blockchain, 
+                                   state: &mut State, 
+                                   schema: &Schema) -> TokioResult<u64> {
+    let mut count = 0;
+
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let block_data: BlockData = serde_json::from_str(&line)?;
+        let bix_last = state.get_last_block_info().bix;
+
+        if block_data.bix <= bix_last {
+            // Existing block must match
+            let hash_local = blockchain.get_block_info(block_data.bix).await?
+                                       .hash;
+            if hash_local != block_data.block.hash {
+                return Err(Error::new(ErrorKind::InvalidData, format!(
+                    "Block {} diverges from the local one", block_data.bix
+                )));
+            }
+        } else if block_data.bix == bix_last + 1 {
+            // New block must be valid
+            validate_block(&block_data, state, schema).map_err(|err| 
+                Error::new(ErrorKind::InvalidData, format!(
+                    "Block {} is invalid: {}", block_data.bix, err
+                ))
+            )?;
+            blockchain.push_new_block(&block_data.block, 
+                                      &block_data.transactions).await?;
+            state.roll_up(block_data.bix, &block_data.block, 
+                          &block_data.transactions, schema);
+            count += 1;
+        } else {
+            return Err(Error::new(ErrorKind::InvalidData, format!(
+                "Block {} is expected but {} found", bix_last + 1, 
+                block_data.bix
+            )));
+        }
+    }
+
+    Ok(count)
+}
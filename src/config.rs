@@ -1,9 +1,33 @@
 use std::env;
+use std::str::FromStr;
+use std::collections::HashMap;
 
 use lbasedb::path_concat;
+use serde_json::Value as JsonValue;
 use uqoin_core::utils::U256;
 use uqoin_core::schema::Schema;
-use uqoin_core::coin::coin_order_by_symbol;
+use uqoin_core::coin::{coin_order_by_symbol, coin_symbol};
+
+use crate::utils::parse_u256;
+use crate::peers::{BanPolicy, parse_node_url};
+use crate::snapshots::Snapshots;
+use crate::ratelimit::RateLimiter;
+
+
+/// Names of the config parameters. They are used as is for the environment
+/// variables and in lower case for the keys in config files.
+const KEYS: &[&str] = &[
+    "HOST", "PORT", "WORKERS", "DATA_PATH", "NODES", "PRIVATE_KEY", 
+    "MINING_THREADS", "FEE_MIN", "NODE_SYNC_TIMEOUT", "NODE_SYNC_BLOCK_COUNT", 
+    "MINING_TIMEOUT", "MINING_UPDATE_COUNT", 
+    "MINING_NONCE_COUNT_PER_ITERATION", "MINING_GROUPS_MAX", "FREE_SPLIT", 
+    "RELAY_HOPS_MAX", "PUBLIC_URL", "NODE_DISCOVERY_TIMEOUT", "NODES_MAX", 
+    "NODE_FAILURES_MAX", "NODE_STRIKES_MAX", "NODE_BAN_TIMEOUT", "ADMIN_TOKEN",
+    "VERIFY_ON_START", "SNAPSHOT_INTERVAL", "SNAPSHOTS_MAX", 
+    "POOL_DUMP_TIMEOUT", "POOL_GROUP_AGE_MAX", "POOL_GROUPS_MAX", 
+    "POOL_SENDER_GROUPS_MAX", "RATE_LIMIT_CAPACITY", "RATE_LIMIT_REFILL", 
+    "RATE_LIMIT_COSTS", "RATE_LIMIT_TRUST_PROXY", "ADMIN_HMAC_SECRET",
+];
 
 
 /// Config parameters.
@@ -58,63 +82,548 @@ pub struct Config {
 
     /// Free split.
     pub free_split: bool,
+
+    /// Maximum number of hops to relay groups between nodes.
+    pub relay_hops_max: u64,
+
+    /// URL of this node for the other nodes (used in block announcements).
+    pub public_url: Option<String>,
+
+    /// Node discovery timeout.
+    pub node_discovery_timeout: u64,
+
+    /// Maximum number of nodes to keep (discovery stops on reaching it).
+    pub nodes_max: usize,
+
+    /// Number of failed requests in a row to ban a node.
+    pub node_failures_max: u64,
+
+    /// Number of invalid blocks served to ban a node.
+    pub node_strikes_max: u64,
+
+    /// Node ban duration.
+    pub node_ban_timeout: u64,
+
+    /// Token to access operator endpoints.
+    pub admin_token: Option<String>,
+
+    /// Secret to sign requests to operator endpoints.
+    pub admin_hmac_secret: Option<String>,
+
+    /// Blockchain verification at startup.
+    pub verify_on_start: VerifyMode,
+
+    /// Number of blocks between state snapshots.
+    pub snapshot_interval: u64,
+
+    /// Maximum number of state snapshots to keep.
+    pub snapshots_max: usize,
+
+    /// Timeout between pool dumps.
+    pub pool_dump_timeout: u64,
+
+    /// Maximum age of the groups in the pool.
+    pub pool_group_age_max: u64,
+
+    /// Maximum number of groups in the pool.
+    pub pool_groups_max: usize,
+
+    /// Maximum number of groups of one sender in the pool.
+    pub pool_sender_groups_max: usize,
+
+    /// Capacity of the rate limit bucket of a client (0 disables the limit).
+    pub rate_limit_capacity: u64,
+
+    /// Rate limit bucket refill (tokens per second).
+    pub rate_limit_refill: u64,
+
+    /// Costs of the routes in rate limit tokens.
+    pub rate_limit_costs: HashMap<String, u64>,
+
+    /// Take the client IP from `X-Forwarded-For` for the rate limit.
+    pub rate_limit_trust_proxy: bool,
+
+    /// Warnings about deprecated values to log on start.
+    pub warnings: Vec<String>,
+}
+
+
+/// Mode of the blockchain verification at startup.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VerifyMode {
+    /// Trust the state file and the blockchain.
+    Off,
+
+    /// Refuse to start if an invalid block or a state mismatch is found.
+    Strict,
+
+    /// Truncate the blockchain at the first invalid block and rebuild the
+    /// state if it does not match.
+    Truncate,
+}
+
+
+impl FromStr for VerifyMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(Self::Off),
+            "strict" => Ok(Self::Strict),
+            "truncate" => Ok(Self::Truncate),
+            _ => Err("off, strict or truncate expected".to_string()),
+        }
+    }
+}
+
+
+impl std::fmt::Display for VerifyMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let name = match self {
+            Self::Off => "off",
+            Self::Strict => "strict",
+            Self::Truncate => "truncate",
+        };
+        write!(f, "{}", name)
+    }
 }
 
 
 impl Config {
-    pub fn from_env() -> Self {
+    /// Load config from the file (TOML or JSON depending on the extension) if
+    /// it is given, then override the values by the environment variables.
+    pub fn load(path: Option<&str>) -> Result<Self, ConfigError> {
+        let mut raw = if let Some(path) = path {
+            RawConfig::from_file(path)?
+        } else {
+            RawConfig::default()
+        };
+        raw.override_env();
+        Self::from_raw(&raw)
+    }
+
+    fn from_raw(raw: &RawConfig) -> Result<Self, ConfigError> {
         let schema = Schema::new();
 
-        let private_key = env::var("PRIVATE_KEY")
-            .map(|s| U256::from_hex(&s)).ok();
+        let private_key = raw.get_str("PRIVATE_KEY").map(|s| 
+            parse_u256(s).ok_or(ConfigError::invalid(
+                "PRIVATE_KEY", "<redacted>", "64 hex digits expected"
+            ))
+        ).transpose()?;
         let public_key = private_key.as_ref()
-            .map(|key| schema.get_public(&key));
+            .map(|key| schema.get_public(key));
 
-        let nodes: Vec<String> = env::var("NODES")
-            .map(|l| l.split_whitespace().map(|s| s.to_string()).collect())
-            .unwrap_or(vec![]);
+        let mut warnings = Vec::new();
 
-        let data_path = env::var("DATA_PATH").unwrap_or("./tmp".to_string());
+        // URLs with path were accepted before the validation, so they are
+        // kept with a warning
+        let mut nodes = Vec::new();
+        for s in raw.get_str("NODES").unwrap_or("").split_whitespace() {
+            if let Some(node) = parse_node_url(s) {
+                nodes.push(node);
+            } else if is_http_url(s) {
+                warnings.push(format!(
+                    "Deprecated value {:?} for `NODES`: URL without path \
+                     expected", s
+                ));
+                nodes.push(s.trim_end_matches('/').to_string());
+            } else {
+                return Err(ConfigError::invalid("NODES", s, 
+                                                "HTTP URL expected"));
+            }
+        }
+
+        let public_url = raw.get_str("PUBLIC_URL").map(|s| 
+            parse_node_url(s).ok_or(
+                ConfigError::invalid("PUBLIC_URL", s, "HTTP URL expected")
+            )
+        ).transpose()?;
+
+        let fee_min_order = raw.get_str("FEE_MIN").map(|s| 
+            parse_coin_symbol(s).map(Ok).unwrap_or_else(|| {
+                // Symbols like `D3` were accepted before the validation
+                let order = parse_coin_symbol_legacy(s).ok_or(
+                    ConfigError::invalid("FEE_MIN", s, "coin symbol expected")
+                )?;
+                warnings.push(format!(
+                    "Deprecated value {:?} for `FEE_MIN`: it is treated as {}",
+                    s, coin_symbol(order)
+                ));
+                Ok(order)
+            })
+        ).transpose()?.unwrap_or(0);
+
+        let rate_limit_costs = raw.get_str("RATE_LIMIT_COSTS")
+            .unwrap_or("/client/coins=10 /blockchain/block-many=10")
+            .split_whitespace().map(|s| s.split_once('=')
+                .and_then(|(path, cost)| Some((path.to_string(), 
+                                               cost.parse().ok()?)))
+                .ok_or(ConfigError::invalid("RATE_LIMIT_COSTS", s, 
+                                            "path=cost expected"))
+            ).collect::<Result<_, _>>()?;
+
+        let rate_limit_capacity = raw.get("RATE_LIMIT_CAPACITY", 0)?;
+        let rate_limit_refill = raw.get("RATE_LIMIT_REFILL", 10)?;
+        ConfigError::check(rate_limit_capacity == 0 || rate_limit_refill > 0, 
+                           "RATE_LIMIT_REFILL", "must be positive")?;
+
+        let data_path = raw.get("DATA_PATH", "./tmp".to_string())?;
 
         let lite_mode = private_key.is_none();
 
-        std::fs::create_dir_all(&data_path).unwrap();
-
-        Self {
-            host: env::var("HOST").unwrap_or("localhost".to_string()),
-            port: env::var("PORT").unwrap_or("5772".to_string())
-                                  .parse().unwrap(),
-            workers: env::var("WORKERS").unwrap_or("1".to_string())
-                                        .parse().unwrap(),
-            data_path, nodes, private_key, public_key,
-            mining_threads: env::var("MINING_THREADS")
-                                .unwrap_or("1".to_string()).parse().unwrap(),
-            fee_min_order: env::var("FEE_MIN")
-                               .map(|s| coin_order_by_symbol(&s)).unwrap_or(0),
-            node_sync_timeout: env::var("NODE_SYNC_TIMEOUT")
-                                   .map(|s| s.parse().unwrap()).unwrap_or(5000),
-            node_sync_block_count: env::var("NODE_SYNC_BLOCK_COUNT")
-                                   .map(|s| s.parse().unwrap()).unwrap_or(1000),
-            mining_timeout: env::var("MINING_TIMEOUT")
-                                .map(|s| s.parse().unwrap()).unwrap_or(20000),
-            mining_update_count: env::var("MINING_UPDATE_COUNT")
-                                 .map(|s| s.parse().unwrap()).unwrap_or(20),
+        let workers = raw.get("WORKERS", 1)?;
+        ConfigError::check(workers > 0, "WORKERS", "must be positive")?;
+
+        let mining_update_count = raw.get("MINING_UPDATE_COUNT", 20)?;
+        ConfigError::check(mining_update_count > 0, "MINING_UPDATE_COUNT", 
+                           "must be positive")?;
+
+        let snapshots_max = raw.get("SNAPSHOTS_MAX", 5)?;
+        ConfigError::check(snapshots_max > 0, "SNAPSHOTS_MAX", 
+                           "must be positive")?;
+
+        let pool_dump_timeout = raw.get("POOL_DUMP_TIMEOUT", 60000)?;
+        ConfigError::check(pool_dump_timeout > 0, "POOL_DUMP_TIMEOUT", 
+                           "must be positive")?;
+
+        let pool_groups_max = raw.get("POOL_GROUPS_MAX", 10000)?;
+        ConfigError::check(pool_groups_max > 0, "POOL_GROUPS_MAX", 
+                           "must be positive")?;
+
+        let pool_sender_groups_max = raw.get("POOL_SENDER_GROUPS_MAX", 100)?;
+        ConfigError::check(pool_sender_groups_max > 0, 
+                           "POOL_SENDER_GROUPS_MAX", "must be positive")?;
+
+        std::fs::create_dir_all(&data_path).map_err(|err| 
+            ConfigError::invalid("DATA_PATH", &data_path, &err.to_string())
+        )?;
+
+        Ok(Self {
+            host: raw.get("HOST", "localhost".to_string())?,
+            port: raw.get("PORT", 5772)?,
+            workers, data_path, nodes, private_key, public_key,
+            mining_threads: raw.get("MINING_THREADS", 1)?,
+            fee_min_order,
+            node_sync_timeout: raw.get("NODE_SYNC_TIMEOUT", 5000)?,
+            node_sync_block_count: raw.get("NODE_SYNC_BLOCK_COUNT", 1000)?,
+            mining_timeout: raw.get("MINING_TIMEOUT", 20000)?,
+            mining_update_count,
             mining_nonce_count_per_iteration: 
-                env::var("MINING_NONCE_COUNT_PER_ITERATION")
-                    .map(|s| s.parse().unwrap()).unwrap_or(100000),
-            mining_groups_max: env::var("MINING_GROUPS_MAX")
-                    .map(|s| s.parse().unwrap()).ok(),
+                raw.get("MINING_NONCE_COUNT_PER_ITERATION", 100000)?,
+            mining_groups_max: raw.get_opt("MINING_GROUPS_MAX")?,
             lite_mode,
-            free_split: env::var("FREE_SPLIT")
-                    .map(|s| s.parse().unwrap()).unwrap_or(true),
+            free_split: raw.get("FREE_SPLIT", true)?,
+            relay_hops_max: raw.get("RELAY_HOPS_MAX", 3)?,
+            public_url,
+            node_discovery_timeout: raw.get("NODE_DISCOVERY_TIMEOUT", 60000)?,
+            nodes_max: raw.get("NODES_MAX", 20)?,
+            node_failures_max: raw.get("NODE_FAILURES_MAX", 5)?,
+            node_strikes_max: raw.get("NODE_STRIKES_MAX", 3)?,
+            node_ban_timeout: raw.get("NODE_BAN_TIMEOUT", 600000)?,
+            admin_token: raw.get_opt("ADMIN_TOKEN")?,
+            admin_hmac_secret: raw.get_opt("ADMIN_HMAC_SECRET")?,
+            verify_on_start: raw.get("VERIFY_ON_START", VerifyMode::Off)?,
+            snapshot_interval: raw.get("SNAPSHOT_INTERVAL", 1000)?,
+            snapshots_max,
+            pool_dump_timeout,
+            pool_group_age_max: raw.get("POOL_GROUP_AGE_MAX", 86400000)?,
+            pool_groups_max,
+            pool_sender_groups_max,
+            rate_limit_capacity,
+            rate_limit_refill,
+            rate_limit_costs,
+            rate_limit_trust_proxy: raw.get("RATE_LIMIT_TRUST_PROXY", false)?,
+            warnings,
+        })
+    }
+
+    /// Represent the effective config in TOML format that is accepted by
+    /// `load`. Secrets are redacted.
+    pub fn to_toml(&self) -> String {
+        let mut table = toml::Table::new();
+        let mut set = |key: &str, value: toml::Value| {
+            table.insert(key.to_lowercase(), value);
+        };
+        let redacted = || toml::Value::from("<redacted>");
+
+        set("HOST", self.host.clone().into());
+        set("PORT", (self.port as i64).into());
+        set("WORKERS", (self.workers as i64).into());
+        set("DATA_PATH", self.data_path.clone().into());
+        set("NODES", self.nodes.clone().into());
+        if self.private_key.is_some() {
+            set("PRIVATE_KEY", redacted());
+        }
+        set("MINING_THREADS", (self.mining_threads as i64).into());
+        if self.fee_min_order > 0 {
+            set("FEE_MIN", coin_symbol(self.fee_min_order).into());
         }
+        set("NODE_SYNC_TIMEOUT", (self.node_sync_timeout as i64).into());
+        set("NODE_SYNC_BLOCK_COUNT", 
+            (self.node_sync_block_count as i64).into());
+        set("MINING_TIMEOUT", (self.mining_timeout as i64).into());
+        set("MINING_UPDATE_COUNT", (self.mining_update_count as i64).into());
+        set("MINING_NONCE_COUNT_PER_ITERATION", 
+            (self.mining_nonce_count_per_iteration as i64).into());
+        if let Some(mining_groups_max) = self.mining_groups_max {
+            set("MINING_GROUPS_MAX", (mining_groups_max as i64).into());
+        }
+        set("FREE_SPLIT", self.free_split.into());
+        set("RELAY_HOPS_MAX", (self.relay_hops_max as i64).into());
+        if let Some(public_url) = self.public_url.as_ref() {
+            set("PUBLIC_URL", public_url.clone().into());
+        }
+        set("NODE_DISCOVERY_TIMEOUT", 
+            (self.node_discovery_timeout as i64).into());
+        set("NODES_MAX", (self.nodes_max as i64).into());
+        set("NODE_FAILURES_MAX", (self.node_failures_max as i64).into());
+        set("NODE_STRIKES_MAX", (self.node_strikes_max as i64).into());
+        set("NODE_BAN_TIMEOUT", (self.node_ban_timeout as i64).into());
+        if self.admin_token.is_some() {
+            set("ADMIN_TOKEN", redacted());
+        }
+        if self.admin_hmac_secret.is_some() {
+            set("ADMIN_HMAC_SECRET", redacted());
+        }
+        set("VERIFY_ON_START", self.verify_on_start.to_string().into());
+        set("SNAPSHOT_INTERVAL", (self.snapshot_interval as i64).into());
+        set("SNAPSHOTS_MAX", (self.snapshots_max as i64).into());
+        set("POOL_DUMP_TIMEOUT", (self.pool_dump_timeout as i64).into());
+        set("POOL_GROUP_AGE_MAX", (self.pool_group_age_max as i64).into());
+        set("POOL_GROUPS_MAX", (self.pool_groups_max as i64).into());
+        set("POOL_SENDER_GROUPS_MAX", 
+            (self.pool_sender_groups_max as i64).into());
+        set("RATE_LIMIT_CAPACITY", (self.rate_limit_capacity as i64).into());
+        set("RATE_LIMIT_REFILL", (self.rate_limit_refill as i64).into());
+        let mut rate_limit_costs: Vec<String> = self.rate_limit_costs.iter()
+            .map(|(path, cost)| format!("{}={}", path, cost)).collect();
+        rate_limit_costs.sort();
+        set("RATE_LIMIT_COSTS", rate_limit_costs.into());
+        set("RATE_LIMIT_TRUST_PROXY", self.rate_limit_trust_proxy.into());
+
+        toml::to_string(&table).unwrap()
     }
 
     pub fn get_state_path(&self) -> String {
         path_concat!(self.data_path.clone(), "state.json")
     }
 
+    pub fn get_nodes_path(&self) -> String {
+        path_concat!(self.data_path.clone(), "nodes.json")
+    }
+
+    pub fn get_pool_path(&self) -> String {
+        path_concat!(self.data_path.clone(), "pool.json")
+    }
+
+    pub fn get_rate_limiter(&self) -> RateLimiter {
+        RateLimiter::new(self.rate_limit_capacity, self.rate_limit_refill, 
+                         self.rate_limit_costs.clone())
+    }
+
+    pub fn get_audit_path(&self) -> String {
+        path_concat!(self.data_path.clone(), "audit.log")
+    }
+
+    pub fn get_snapshots(&self) -> Snapshots {
+        Snapshots::new(path_concat!(self.data_path.clone(), "snapshots"), 
+                       self.snapshot_interval, self.snapshots_max)
+    }
+
+    pub fn get_ban_policy(&self) -> BanPolicy {
+        BanPolicy {
+            failures_max: self.node_failures_max,
+            strikes_max: self.node_strikes_max,
+            timeout: self.node_ban_timeout,
+        }
+    }
+
     pub fn get_mining_validate_iter_timeout(&self) -> u64 {
         self.mining_timeout / self.mining_update_count
     }
 }
+
+
+/// Error in config parameters.
+#[derive(Debug)]
+pub struct ConfigError(String);
+
+
+impl ConfigError {
+    fn invalid(key: &str, value: &str, reason: &str) -> Self {
+        Self(format!("Invalid value {:?} for `{}`: {}", value, key, reason))
+    }
+
+    fn check(cond: bool, key: &str, reason: &str) -> Result<(), Self> {
+        if cond {
+            Ok(())
+        } else {
+            Err(Self(format!("Invalid value for `{}`: {}", key, reason)))
+        }
+    }
+}
+
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+
+impl std::error::Error for ConfigError {}
+
+
+/// Raw string values of the config parameters by the keys from `KEYS`.
+#[derive(Debug, Default)]
+struct RawConfig(HashMap<String, String>);
+
+
+impl RawConfig {
+    /// Read values from TOML or JSON file. The keys in the file are the 
+    /// lower case `KEYS`, lists are joined with spaces.
+    fn from_file(path: &str) -> Result<Self, ConfigError> {
+        let content = std::fs::read_to_string(path).map_err(|err| 
+            ConfigError(format!("Could not read config {}: {}", path, err))
+        )?;
+
+        let root: JsonValue = if path.ends_with(".json") {
+            serde_json::from_str(&content).map_err(|err| 
+                ConfigError(format!("Could not parse {}: {}", path, err))
+            )?
+        } else {
+            let table: toml::Table = toml::from_str(&content).map_err(|err| 
+                ConfigError(format!("Could not parse {}: {}", path, err))
+            )?;
+            serde_json::to_value(table).unwrap()
+        };
+
+        let JsonValue::Object(object) = root else {
+            return Err(ConfigError(format!("Config {} must be a table", path)));
+        };
+
+        let mut values = HashMap::new();
+        for (name, value) in object.into_iter() {
+            let key = name.to_uppercase();
+            if !KEYS.contains(&key.as_str()) {
+                return Err(ConfigError(format!("Unknown key `{}` in {}", 
+                                               name, path)));
+            }
+            let value = Self::value_to_string(&value).ok_or(ConfigError(
+                format!("Invalid value {} for `{}` in {}", value, name, path)
+            ))?;
+            values.insert(key, value);
+        }
+
+        Ok(Self(values))
+    }
+
+    /// Override values by the environment variables.
+    fn override_env(&mut self) {
+        for key in KEYS.iter() {
+            if let Ok(value) = env::var(key) {
+                self.0.insert(key.to_string(), value);
+            }
+        }
+    }
+
+    fn get_str(&self, key: &str) -> Option<&str> {
+        self.0.get(key).map(|s| s.as_str())
+    }
+
+    fn get_opt<T: FromStr>(&self, key: &str) -> Result<Option<T>, ConfigError> 
+            where T::Err: std::fmt::Display {
+        self.get_str(key).map(|s| s.parse().map_err(|err: T::Err| 
+            ConfigError::invalid(key, s, &err.to_string())
+        )).transpose()
+    }
+
+    fn get<T: FromStr>(&self, key: &str, default: T) -> Result<T, ConfigError> 
+            where T::Err: std::fmt::Display {
+        Ok(self.get_opt(key)?.unwrap_or(default))
+    }
+
+    fn value_to_string(value: &JsonValue) -> Option<String> {
+        match value {
+            JsonValue::String(s) => Some(s.clone()),
+            JsonValue::Number(n) => Some(n.to_string()),
+            JsonValue::Bool(b) => Some(b.to_string()),
+            JsonValue::Array(items) => items.iter().map(|item| match item {
+                JsonValue::Array(_) => None,
+                _ => Self::value_to_string(item),
+            }).collect::<Option<Vec<String>>>().map(|items| items.join(" ")),
+            _ => None,
+        }
+    }
+}
+
+
+/// Parse coin symbol like `D1` or `C32` into the order.
+fn parse_coin_symbol(symbol: &str) -> Option<u64> {
+    let mut chars = symbol.chars();
+    let is_valid = chars.next().is_some_and(|c| c.is_ascii_uppercase()) && 
+        chars.as_str().parse::<u64>()
+             .is_ok_and(|n| n.is_power_of_two() && n < 1024);
+    is_valid.then(|| coin_order_by_symbol(symbol))
+}
+
+
+/// Parse coin symbol the way it was done before the validation: the number is
+/// not required to be a power of two (`D3` is `D1`, `D12` is `D4`).
+fn parse_coin_symbol_legacy(symbol: &str) -> Option<u64> {
+    let mut chars = symbol.chars();
+    let is_valid = chars.next().is_some_and(|c| c.is_ascii_uppercase()) && 
+        chars.as_str().parse::<u64>().is_ok_and(|n| n > 0);
+    is_valid.then(|| coin_order_by_symbol(symbol))
+}
+
+
+/// Check if the string is an HTTP or HTTPS URL with a host.
+fn is_http_url(url: &str) -> bool {
+    reqwest::Url::parse(url).is_ok_and(|parsed| 
+        (parsed.scheme() == "http" || parsed.scheme() == "https") && 
+        parsed.host_str().is_some()
+    )
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_coin_symbol() {
+        assert_eq!(parse_coin_symbol("D1"), Some(30));
+        assert_eq!(parse_coin_symbol("C32"), Some(25));
+        assert_eq!(parse_coin_symbol("C3"), None);
+        assert_eq!(parse_coin_symbol("d1"), None);
+        assert_eq!(parse_coin_symbol("D"), None);
+        assert_eq!(parse_coin_symbol(""), None);
+
+        assert_eq!(parse_coin_symbol_legacy("D1"), Some(30));
+        assert_eq!(parse_coin_symbol_legacy("C3"), Some(20));
+        assert_eq!(parse_coin_symbol_legacy("C12"), Some(22));
+        assert_eq!(parse_coin_symbol_legacy("C0"), None);
+        assert_eq!(parse_coin_symbol_legacy("d1"), None);
+    }
+
+    #[test]
+    fn test_raw_config() {
+        let table: toml::Table = toml::from_str(
+            "port = 6000\nfree_split = false\n\
+             nodes = [\"http://a\", \"http://b\"]"
+        ).unwrap();
+        let value = serde_json::to_value(table).unwrap();
+        let values: HashMap<String, String> = value.as_object().unwrap().iter()
+            .map(|(k, v)| (k.to_uppercase(), 
+                           RawConfig::value_to_string(v).unwrap()))
+            .collect();
+        let raw = RawConfig(values);
+
+        assert_eq!(raw.get::<u16>("PORT", 5772).unwrap(), 6000);
+        assert_eq!(raw.get::<usize>("WORKERS", 1).unwrap(), 1);
+        assert!(!raw.get("FREE_SPLIT", true).unwrap());
+        assert_eq!(raw.get_str("NODES"), Some("http://a http://b"));
+        assert!(raw.get::<bool>("NODES", true).is_err());
+    }
+}
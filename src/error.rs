@@ -1,54 +1,224 @@
 use serde::Serialize;
-use actix_web::{ResponseError, HttpResponse};
-use actix_web::http::{StatusCode, header::ContentType};
+use serde_json::Value as JsonValue;
+use actix_web::{ResponseError, HttpRequest, HttpResponse};
+use actix_web::http::{StatusCode, header::{ContentType, RETRY_AFTER}};
+use uqoin_core::error::ErrorKind;
 
 
+/// Machine-readable code of the API error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum ErrorCode {
+    /// Malformed request.
+    BadRequest,
+
+    /// Query misses required parameters.
+    Query,
+
+    /// Transactions do not form a valid group.
+    InvalidGroup,
+
+    /// Invalid URL of the node.
+    InvalidNode,
+
+    /// The node could not be reached or has a different blockchain.
+    UnreachableNode,
+
+    /// Invalid number of mining threads.
+    InvalidThreads,
+
+    /// Block number is out of range.
+    InvalidBix,
+
+    /// Invalid value of the parameter.
+    InvalidValue,
+
+    /// Operator credentials are missing or invalid.
+    Unauthorized,
+
+    /// Fee of the group is too low.
+    Fee,
+
+    /// The pool is full of groups with higher fees.
+    PoolFull,
+
+    /// The node does not mine, so it does not accept groups.
+    LiteMode,
+
+    /// Requested block, transaction or coin does not exist.
+    NotFound,
+
+    /// The node is not in the list of known nodes.
+    UnknownNode,
+
+    /// A coin of the group is already spent by another pending group.
+    DoubleSpend,
+
+    /// The sender has too many pending groups.
+    SenderLimit,
+
+    /// The client exceeded the rate limit.
+    TooManyRequests,
+
+    /// Failure of the storage (blockchain, state or other files).
+    Storage,
+
+    /// Unexpected failure of the node.
+    Internal,
+
+    /// The node is syncing, so the state is not up to date.
+    Syncing,
+
+    /// The node is shutting down.
+    ShuttingDown,
+}
+
+
+impl ErrorCode {
+    /// HTTP status of the error.
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Self::BadRequest | Self::Query | Self::InvalidGroup |
+            Self::InvalidNode | Self::UnreachableNode | Self::InvalidThreads |
+            Self::InvalidBix | Self::InvalidValue => StatusCode::BAD_REQUEST,
+            Self::Unauthorized => StatusCode::UNAUTHORIZED,
+            Self::Fee | Self::PoolFull => StatusCode::PAYMENT_REQUIRED,
+            Self::LiteMode => StatusCode::FORBIDDEN,
+            Self::NotFound | Self::UnknownNode => StatusCode::NOT_FOUND,
+            Self::DoubleSpend => StatusCode::CONFLICT,
+            Self::SenderLimit | Self::TooManyRequests =>
+                StatusCode::TOO_MANY_REQUESTS,
+            Self::Storage | Self::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::Syncing | Self::ShuttingDown =>
+                StatusCode::SERVICE_UNAVAILABLE,
+        }
+    }
+}
+
+
+/// Error returned by the API as `{"code": ..., "detail": ...}` with optional
+/// `details` object.
 #[derive(Debug, Serialize)]
 pub struct JsonError {
+    code: ErrorCode,
+
     detail: String,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    details: Option<JsonValue>,
+
+    #[serde(skip)]
+    retry_after: Option<u64>,
 }
 
 
 impl JsonError {
-    pub fn new(detail: &str) -> Self {
-        Self { detail: detail.to_string() }
+    /// Error with the code as the detail.
+    pub fn new(code: ErrorCode) -> Self {
+        Self::with_detail(code, &format!("{:?}", code))
+    }
+
+    /// Error with a custom detail message.
+    pub fn with_detail(code: ErrorCode, detail: &str) -> Self {
+        Self { code, detail: detail.to_string(), details: None,
+               retry_after: None }
+    }
+
+    /// Attach extra fields to the error (e.g. the required fee).
+    pub fn details(mut self, details: JsonValue) -> Self {
+        self.details = Some(details);
+        self
+    }
+
+    /// Error for the client exceeded the rate limit (429). The client may
+    /// retry after `retry_after` seconds.
+    pub fn too_many_requests(retry_after: u64) -> Self {
+        Self { retry_after: Some(retry_after),
+               ..Self::new(ErrorCode::TooManyRequests) }
     }
 }
 
 
 impl std::fmt::Display for JsonError {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "{}", self.detail)
+        write!(f, "{:?}: {}", self.code, self.detail)
     }
 }
 
 
-impl<E: std::error::Error> From<E> for JsonError {
-    fn from(err: E) -> Self {
-        Self::new(&err.to_string())
+impl From<std::io::Error> for JsonError {
+    fn from(err: std::io::Error) -> Self {
+        Self::with_detail(ErrorCode::Storage, &err.to_string())
+    }
+}
+
+
+impl From<uqoin_core::error::Error> for JsonError {
+    fn from(err: uqoin_core::error::Error) -> Self {
+        // Only the errors of the coins and transactions are caused by the
+        // client
+        let code = match err.kind() {
+            ErrorKind::CoinInvalid | ErrorKind::CoinNotUnique | 
+            ErrorKind::CoinTooCheap | ErrorKind::TransactionInvalidSender | 
+            ErrorKind::TransactionEmpty | ErrorKind::TransactionBrokenGroup | 
+            ErrorKind::TransactionBrokenExt => ErrorCode::InvalidGroup,
+            _ => ErrorCode::Internal,
+        };
+        Self::with_detail(code, &err.to_string())
     }
 }
 
 
 impl ResponseError for JsonError {
     fn error_response(&self) -> HttpResponse {
-        HttpResponse::build(self.status_code())
-            .insert_header(ContentType::json())
-            .json(self)
+        let mut response = HttpResponse::build(self.status_code());
+        response.insert_header(ContentType::json());
+        if let Some(retry_after) = self.retry_after {
+            response.insert_header((RETRY_AFTER, retry_after));
+        }
+        response.json(self)
     }
 
     fn status_code(&self) -> StatusCode {
-        StatusCode::BAD_REQUEST
+        self.code.status()
     }
 }
 
 
-/// Check condition and raise API error.
+/// Error handler for malformed queries and JSON bodies, so they are
+/// reported as `BadRequest` in the same format.
+pub fn bad_request<E: std::fmt::Display>(err: E, 
+                                         _: &HttpRequest) -> actix_web::Error {
+    JsonError::with_detail(ErrorCode::BadRequest, &err.to_string()).into()
+}
+
+
+/// Check condition and raise API error with the code.
 #[macro_export]
 macro_rules! api_check {
-    ($cond:expr, $detail:expr) => {
+    ($cond:expr, $code:ident) => {
         if !$cond {
-            return Err(crate::error::JsonError::new($detail));
+            return Err($crate::error::JsonError::new(
+                $crate::error::ErrorCode::$code
+            ));
         }
     }
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_core_error() {
+        let err: JsonError = uqoin_core::error::Error::from(
+            ErrorKind::TransactionBrokenGroup
+        ).into();
+        assert_eq!(err.code, ErrorCode::InvalidGroup);
+
+        let err: JsonError = uqoin_core::error::Error::from(
+            ErrorKind::BlockInvalidHash
+        ).into();
+        assert_eq!(err.code, ErrorCode::Internal);
+    }
+}
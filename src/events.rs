@@ -0,0 +1,358 @@
+use std::convert::Infallible;
+
+use actix_web::HttpResponse;
+use actix_web::http::header::CACHE_CONTROL;
+use actix_web::web::Bytes;
+use futures_util::Stream;
+use futures_util::stream::unfold;
+use serde::Serialize;
+use serde_json::json;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::time::{timeout, Duration};
+use uqoin_core::utils::U256;
+use uqoin_core::block::{Block, BlockInfo};
+use uqoin_core::transaction::{Transaction, Type};
+
+use crate::utils::*;
+use crate::pool::GroupStatus;
+use crate::chain::CoinMove;
+
+
+/// Number of events kept for slow subscribers.
+const EVENTS_CAPACITY: usize = 1024;
+
+/// Interval of keep-alive comments in the event stream (in milliseconds).
+const KEEP_ALIVE_INTERVAL: u64 = 15000;
+
+
+/// Event of the node pushed to the subscribers.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    /// New block is added to the blockchain.
+    BlockAdded {
+        bix: u64,
+        hash: U256,
+        offset: u64,
+        validator: U256,
+        transactions: usize,
+    },
+
+    /// Last `depth` blocks are replaced moving the tip from `old_tip` to
+    /// `new_tip`.
+    Reorg {
+        old_tip: BlockInfo,
+        new_tip: BlockInfo,
+        depth: u64,
+    },
+
+    /// The group is added to the pool.
+    PoolAdded {
+        group: U256,
+        sender: U256,
+        fee_order: u64,
+    },
+
+    /// The group left the pool with the status.
+    PoolRemoved {
+        group: U256,
+
+        #[serde(flatten)]
+        status: GroupStatus,
+    },
+
+    /// The syncing flag is changed.
+    SyncStatus {
+        is_syncing: bool,
+    },
+
+    /// Coins of the wallet are changed by the block `bix` (or by its
+    /// rollback if `reverted`).
+    CoinsChanged {
+        wallet: U256,
+        bix: u64,
+        reverted: bool,
+        added: Vec<CoinDelta>,
+        removed: Vec<CoinDelta>,
+    },
+}
+
+
+/// Coin moved by the transaction `tix`.
+#[derive(Debug, Clone, Serialize)]
+pub struct CoinDelta {
+    pub coin: U256,
+    pub order: u64,
+    pub tix: u64,
+}
+
+
+impl Event {
+    /// Name of the event in the stream.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::BlockAdded { .. } => "block_added",
+            Self::Reorg { .. } => "reorg",
+            Self::PoolAdded { .. } => "pool_added",
+            Self::PoolRemoved { .. } => "pool_removed",
+            Self::SyncStatus { .. } => "sync_status",
+            Self::CoinsChanged { .. } => "coins_changed",
+        }
+    }
+}
+
+
+/// Event with the wallets it concerns. Events without wallets concern
+/// everybody.
+#[derive(Debug, Clone)]
+pub struct Envelope {
+    pub event: Event,
+    pub wallets: Vec<U256>,
+}
+
+
+impl Envelope {
+    /// Event concerning the wallets.
+    pub fn new(event: Event, wallets: Vec<U256>) -> Self {
+        Self { event, wallets }
+    }
+
+    /// Event concerning everybody.
+    pub fn global(event: Event) -> Self {
+        Self::new(event, Vec::new())
+    }
+
+    /// `block_added` event concerning the validator, the senders and the
+    /// receivers of the block.
+    pub fn block_added(bix: u64, block: &Block, transactions: &[Transaction],
+                       senders: &[U256]) -> Self {
+        let mut wallets = transaction_wallets(transactions, senders);
+        if !wallets.contains(&block.validator) {
+            wallets.push(block.validator.clone());
+        }
+        let event = Event::BlockAdded {
+            bix,
+            hash: block.hash.clone(),
+            offset: block.offset + block.size,
+            validator: block.validator.clone(),
+            transactions: transactions.len(),
+        };
+        Self::new(event, wallets)
+    }
+
+    /// Check if the event concerns the wallet (any event if `None`).
+    pub fn concerns(&self, wallet: Option<&U256>) -> bool {
+        match wallet {
+            Some(wallet) => self.wallets.is_empty() ||
+                            self.wallets.contains(wallet),
+            None => true,
+        }
+    }
+
+    /// Message of the event in Server-Sent Events format.
+    fn to_message(&self) -> String {
+        let data = serde_json::to_string(&self.event).unwrap_or_default();
+        format!("event: {}\ndata: {}\n\n", self.event.name(), data)
+    }
+}
+
+
+/// Broadcast channel of the node events.
+#[derive(Debug, Clone)]
+pub struct Events {
+    sender: broadcast::Sender<Envelope>,
+}
+
+
+impl Default for Events {
+    fn default() -> Self {
+        Self { sender: broadcast::Sender::new(EVENTS_CAPACITY) }
+    }
+}
+
+
+impl Events {
+    /// Send the event to the subscribers if there are any.
+    pub fn emit(&self, envelope: Envelope) {
+        let _ = self.sender.send(envelope);
+    }
+
+    /// Check if there are subscribers.
+    pub fn is_active(&self) -> bool {
+        self.sender.receiver_count() > 0
+    }
+
+    /// Subscribe to the events emitted from now.
+    pub fn subscribe(&self) -> broadcast::Receiver<Envelope> {
+        self.sender.subscribe()
+    }
+}
+
+
+/// Wallets of the senders and the receivers of the transactions.
+pub fn transaction_wallets(transactions: &[Transaction],
+                           senders: &[U256]) -> Vec<U256> {
+    let mut wallets: Vec<U256> = Vec::new();
+    let receivers = transactions.iter()
+        .filter(|tr| tr.get_type() == Type::Transfer)
+        .map(|tr| &tr.addr);
+    for wallet in senders.iter().chain(receivers) {
+        if !wallets.contains(wallet) {
+            wallets.push(wallet.clone());
+        }
+    }
+    wallets
+}
+
+
+/// `coins_changed` events for the wallets from the coin moves of the block
+/// `bix` (see `chain::coin_moves`). If `reverted` is set, the block is rolled
+/// down, so the coins move back.
+pub fn coin_deltas(bix: u64, moves: &[CoinMove], 
+                   reverted: bool) -> Vec<Envelope> {
+    let mut changes: Vec<(U256, Vec<CoinDelta>, Vec<CoinDelta>)> = Vec::new();
+
+    for mv in moves.iter() {
+        let ix = match changes.iter().position(|(w, _, _)| w == &mv.wallet) {
+            Some(ix) => ix,
+            None => {
+                changes.push((mv.wallet.clone(), Vec::new(), Vec::new()));
+                changes.len() - 1
+            },
+        };
+        let delta = CoinDelta { 
+            coin: mv.coin.clone(), order: mv.order, tix: mv.tix 
+        };
+        if mv.is_incoming != reverted {
+            changes[ix].1.push(delta);
+        } else {
+            changes[ix].2.push(delta);
+        }
+    }
+
+    changes.into_iter().map(|(wallet, added, removed)| {
+        let event = Event::CoinsChanged {
+            wallet: wallet.clone(), bix, reverted, added, removed
+        };
+        Envelope::new(event, vec![wallet])
+    }).collect()
+}
+
+
+/// Response streaming the events passing the filter in Server-Sent Events
+/// format.
+pub fn response<F>(appdata: WebAppData, filter: F) -> HttpResponse 
+                   where F: Fn(&Envelope) -> bool + 'static {
+    HttpResponse::Ok().content_type("text/event-stream")
+                      .insert_header((CACHE_CONTROL, "no-cache"))
+                      .streaming(stream(appdata, filter))
+}
+
+
+/// Stream of the events passing the filter in Server-Sent Events format.
+/// Keep-alive comments are sent meanwhile, the stream ends on shutdown.
+fn stream<F>(appdata: WebAppData, filter: F) -> 
+             impl Stream<Item = Result<Bytes, Infallible>> 
+             where F: Fn(&Envelope) -> bool + 'static {
+    let receiver = appdata.events.subscribe();
+    unfold((appdata, receiver, filter), 
+           async |(appdata, mut receiver, filter)| {
+        loop {
+            let interval = Duration::from_millis(KEEP_ALIVE_INTERVAL);
+            let message = tokio::select! {
+                result = timeout(interval, receiver.recv()) => {
+                    match result {
+                        Ok(Ok(envelope)) => {
+                            if !filter(&envelope) {
+                                continue;
+                            }
+                            envelope.to_message()
+                        },
+                        Ok(Err(RecvError::Lagged(skipped))) => format!(
+                            "event: lagged\ndata: {}\n\n",
+                            json!({ "type": "lagged", "skipped": skipped })
+                        ),
+                        Ok(Err(RecvError::Closed)) => return None,
+                        Err(_) => ": keep-alive\n\n".to_string(),
+                    }
+                },
+                _ = appdata.shutdown_signalled() => return None,
+            };
+            let state = (appdata, receiver, filter);
+            return Some((Ok(Bytes::from(message)), state));
+        }
+    })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use uqoin_core::state::State;
+
+    use super::*;
+    use crate::chain::coin_moves;
+
+    #[test]
+    fn test_envelope() {
+        let wallet = U256::from(1);
+        let envelope = Envelope::new(Event::PoolRemoved {
+            group: U256::from(2),
+            status: GroupStatus::Confirmed { bix: 3, tix: 4 },
+        }, vec![wallet.clone()]);
+
+        assert!(envelope.concerns(None));
+        assert!(envelope.concerns(Some(&wallet)));
+        assert!(!envelope.concerns(Some(&U256::from(5))));
+
+        let data = serde_json::to_value(&envelope.event).unwrap();
+        assert_eq!(data["type"], "pool_removed");
+        assert_eq!(data["status"], "confirmed");
+        assert_eq!(data["tix"], 4);
+        assert!(envelope.to_message().starts_with("event: pool_removed\n"));
+
+        let envelope = Envelope::global(Event::SyncStatus { is_syncing: true });
+        assert!(envelope.concerns(Some(&wallet)));
+    }
+
+    #[test]
+    fn test_coin_deltas() {
+        let (sender, receiver, validator) = 
+            (U256::from(10), U256::from(11), U256::from(12));
+        let block = Block::new(5, 2, U256::from(0), validator.clone(), 
+                               U256::from(0), U256::from(0));
+        let transactions = vec![
+            Transaction::new(U256::from(100), receiver.clone(), U256::from(0),
+                             U256::from(0)),
+            Transaction::new(U256::from(101), U256::from(0), U256::from(0),
+                             U256::from(0)),
+        ];
+        let senders = vec![sender.clone(), sender.clone()];
+        let moves = coin_moves(&block, &transactions, &senders, 
+                               &State::new());
+
+        // New coins are added to the receiver and the validator
+        let envelopes = coin_deltas(3, &moves, false);
+        assert_eq!(envelopes.len(), 2);
+        assert_eq!(envelopes[0].wallets, vec![receiver.clone()]);
+        assert_eq!(envelopes[1].wallets, vec![validator]);
+        if let Event::CoinsChanged { added, removed, reverted, .. } = 
+                &envelopes[0].event {
+            assert!(!reverted);
+            assert_eq!(added.len(), 1);
+            assert_eq!(added[0].tix, 6);
+            assert!(removed.is_empty());
+        } else {
+            panic!("Unexpected event");
+        }
+
+        // Rollback removes them
+        let envelopes = coin_deltas(3, &moves, true);
+        if let Event::CoinsChanged { added, removed, .. } = 
+                &envelopes[1].event {
+            assert!(added.is_empty());
+            assert_eq!(removed[0].tix, 7);
+        } else {
+            panic!("Unexpected event");
+        }
+    }
+}
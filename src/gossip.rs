@@ -0,0 +1,85 @@
+use log::warn;
+use serde::{Serialize, Deserialize};
+use uqoin_core::utils::U256;
+use uqoin_core::block::BlockInfo;
+use uqoin_core::transaction::Transaction;
+
+use crate::utils::*;
+use crate::remote::post_node;
+
+
+/// Group relayed between nodes.
+#[derive(Serialize, Deserialize)]
+pub struct RelayRequest {
+    /// Transactions of the group.
+    pub transactions: Vec<Transaction>,
+
+    /// Number of hops made by the group including the current one.
+    pub hops: u64,
+}
+
+
+/// Relay the group to the known nodes in background. Nothing is sent if
+/// `hops` exceeds `relay_hops_max`, so the group does not travel forever.
+pub fn relay_group(appdata: &WebAppData, transactions: Vec<Transaction>, 
+                   hops: u64) {
+    if hops > appdata.config.relay_hops_max {
+        return;
+    }
+
+    let appdata = appdata.clone();
+
+    actix_web::rt::spawn(async move {
+        let nodes = appdata.nodes.read().await.list_active();
+        let request = RelayRequest { transactions, hops };
+        for node in nodes.iter() {
+            if let Err(err) = post_node(node, "/node/relay", &request).await {
+                warn!("Could not relay group to {}: {}", node, err);
+            }
+        }
+    });
+}
+
+
+/// Announcement of a new last block sent to the other nodes.
+#[derive(Serialize, Deserialize)]
+pub struct BlockAnnouncement {
+    /// URL of the announcing node (as it is known by the other nodes).
+    pub node: String,
+
+    /// Number of the block.
+    pub bix: u64,
+
+    /// Hash of the block.
+    pub hash: U256,
+
+    /// Total number of transactions up to the block.
+    pub offset: u64,
+}
+
+
+/// Announce the new last block to the known nodes in background, so they can
+/// sync immediately. Nothing is sent if `public_url` is not configured.
+pub fn announce_block(appdata: &WebAppData, block_info: &BlockInfo) {
+    let Some(public_url) = appdata.config.public_url.clone() else {
+        return;
+    };
+
+    let appdata = appdata.clone();
+    let announcement = BlockAnnouncement {
+        node: public_url,
+        bix: block_info.bix,
+        hash: block_info.hash.clone(),
+        offset: block_info.offset,
+    };
+
+    actix_web::rt::spawn(async move {
+        let nodes = appdata.nodes.read().await.list_active();
+        for node in nodes.iter() {
+            if let Err(err) = post_node(node, "/node/announce", 
+                                        &announcement).await {
+                warn!("Could not announce block to {}: {}", node, err);
+            }
+        }
+    });
+}
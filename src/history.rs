@@ -0,0 +1,369 @@
+use std::collections::HashMap;
+
+use log::{info, warn};
+use serde::{Serialize, Deserialize};
+use tokio::sync::Mutex;
+use lbasedb::col::Col;
+use lbasedb::path_concat;
+use uqoin_core::utils::U256;
+use uqoin_core::state::State;
+use uqoin_core::schema::Schema;
+use uqoin_core::block::BlockInfo;
+use uqoin_core::blockchain::Blockchain;
+use uqoin_core::transaction::Transaction;
+
+use crate::utils::*;
+use crate::snapshots::Snapshots;
+use crate::chain::{CoinMove, coin_moves, build_state_at};
+
+
+/// Number of records read from the index at once.
+const READ_CHUNK_SIZE: usize = 10000;
+
+/// Number of blocks read from the blockchain at once on indexing.
+const INDEX_CHUNK_SIZE: u64 = 1000;
+
+
+/// Record of the index stored in the file as raw bytes.
+#[derive(Debug, Clone)]
+#[repr(C)]
+struct Record {
+    wallet: U256,
+    coin: U256,
+    bix: u64,
+    tix: u64,
+    order: u64,
+
+    /// `1` for incoming coins, `0` for outgoing ones.
+    incoming: u64,
+
+    /// Position of the previous record of the wallet plus one (`0` if none).
+    prev: u64,
+}
+
+
+/// Direction of the coin move.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    In,
+    Out,
+}
+
+
+/// Coin move in the wallet history.
+#[derive(Debug, Clone, Serialize)]
+pub struct HistoryItem {
+    pub bix: u64,
+    pub tix: u64,
+    pub coin: U256,
+    pub order: u64,
+    pub direction: Direction,
+}
+
+
+impl From<&Record> for HistoryItem {
+    fn from(record: &Record) -> Self {
+        let direction = if record.incoming == 1 {
+            Direction::In
+        } else {
+            Direction::Out
+        };
+        Self {
+            bix: record.bix, tix: record.tix, coin: record.coin.clone(),
+            order: record.order, direction,
+        }
+    }
+}
+
+
+/// Last indexed block.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct HistoryMeta {
+    bix: u64,
+    hash: U256,
+}
+
+
+impl HistoryMeta {
+    fn genesis() -> Self {
+        Self { bix: 0, hash: BlockInfo::genesis().hash }
+    }
+}
+
+
+/// Persistent index of the coin moves by wallets. The records are appended
+/// in the order of blocks, every record refers to the previous record of its
+/// wallet, so the history of a wallet is read from the newest record. The
+/// last records of the wallets are kept in memory.
+pub struct History {
+    col: Mutex<Col<Record>>,
+    heads: HashMap<U256, u64>,
+    meta: HistoryMeta,
+    meta_path: String,
+}
+
+
+impl History {
+    /// Open the index located in `path`.
+    pub async fn new(path: &str) -> TokioResult<Self> {
+        let col = Mutex::new(Col::new(path_concat!(path, "history.col"))
+                                 .await?);
+        let meta_path = path_concat!(path, "history.json");
+        Ok(Self { col, heads: HashMap::new(), meta: HistoryMeta::genesis(),
+                  meta_path })
+    }
+
+    /// Load the index and bring it in line with the blockchain. The index is
+    /// rebuilt from scratch if it does not match the blockchain.
+    pub async fn load(&mut self, blockchain: &Blockchain, schema: &Schema,
+                      snapshots: &Snapshots) -> TokioResult<()> {
+        let meta: Option<HistoryMeta> = tokio::fs::read(&self.meta_path).await
+            .ok().and_then(|content| serde_json::from_slice(&content).ok());
+        let block_count = blockchain.get_block_count().await?;
+        let is_loaded = meta.is_some();
+
+        let is_valid = match meta.as_ref() {
+            Some(meta) if meta.bix == 0 => true,
+            Some(meta) if meta.bix <= block_count =>
+                blockchain.get_block_info(meta.bix).await?.hash == meta.hash,
+            _ => false,
+        };
+
+        if let Some(meta) = meta && is_valid {
+            // Drop the records written after the last saved block
+            self.load_heads().await?;
+            self.truncate(meta.bix, blockchain).await?;
+            info!("History loaded, bix = {}", self.meta.bix);
+        } else {
+            if is_loaded {
+                warn!("History does not match the blockchain, rebuilding");
+            }
+            self.col.lock().await.resize(0).await?;
+            self.heads.clear();
+            self.meta = HistoryMeta::genesis();
+        }
+
+        if self.meta.bix < block_count {
+            self.index(block_count, blockchain, schema, snapshots).await?;
+            info!("History is indexed up to bix = {}", block_count);
+        }
+
+        self.dump_meta().await
+    }
+
+    /// Append the coin moves of the block `bix` with the hash.
+    pub async fn push(&mut self, bix: u64, hash: &U256,
+                      moves: &[CoinMove]) -> TokioResult<()> {
+        self.append(bix, moves).await?;
+        self.meta = HistoryMeta { bix, hash: hash.clone() };
+        self.dump_meta().await
+    }
+
+    /// Remove the records of the blocks after `bix`.
+    pub async fn truncate(&mut self, bix: u64,
+                          blockchain: &Blockchain) -> TokioResult<()> {
+        let mut col = self.col.lock().await;
+        let mut size = col.size().await?;
+
+        // Walk the records from the end restoring the heads of the wallets
+        'outer: while size > 0 {
+            let offset = size.saturating_sub(READ_CHUNK_SIZE);
+            let records = col.get_many(offset, size - offset).await?;
+            for record in records.iter().rev() {
+                if record.bix <= bix {
+                    break 'outer;
+                }
+                if record.prev > 0 {
+                    self.heads.insert(record.wallet.clone(), record.prev - 1);
+                } else {
+                    self.heads.remove(&record.wallet);
+                }
+                size -= 1;
+            }
+        }
+
+        col.resize(size).await?;
+        drop(col);
+
+        let hash = blockchain.get_block_info(bix).await?.hash;
+        self.meta = HistoryMeta { bix, hash };
+        self.dump_meta().await
+    }
+
+    /// Get at most `limit` items of the wallet history from the newest ones.
+    /// The history is continued from `cursor` returned with the previous
+    /// items. It returns `None` if the cursor does not belong to the wallet.
+    pub async fn get(&self, wallet: &U256, cursor: Option<u64>,
+                     limit: usize) ->
+                     TokioResult<Option<(Vec<HistoryItem>, Option<u64>)>> {
+        let mut col = self.col.lock().await;
+        let size = col.size().await? as u64;
+        let mut next = match cursor {
+            Some(cursor) if cursor > size => return Ok(None),
+            Some(cursor) => cursor,
+            None => self.heads.get(wallet).map(|pos| pos + 1).unwrap_or(0),
+        };
+
+        let mut items = Vec::new();
+        while next > 0 && items.len() < limit {
+            let record = col.get(next as usize - 1).await?;
+            if &record.wallet != wallet {
+                return Ok(None);
+            }
+            items.push(HistoryItem::from(&record));
+            next = record.prev;
+        }
+
+        Ok(Some((items, (next > 0).then_some(next))))
+    }
+
+    async fn append(&mut self, bix: u64,
+                    moves: &[CoinMove]) -> TokioResult<()> {
+        let mut col = self.col.lock().await;
+        let mut pos = col.size().await? as u64;
+        let mut records = Vec::with_capacity(moves.len());
+        for mv in moves.iter() {
+            let prev = self.heads.insert(mv.wallet.clone(), pos)
+                .map(|head| head + 1).unwrap_or(0);
+            records.push(Record {
+                wallet: mv.wallet.clone(), coin: mv.coin.clone(), bix,
+                tix: mv.tix, order: mv.order, incoming: mv.is_incoming as u64,
+                prev,
+            });
+            pos += 1;
+        }
+        if !records.is_empty() {
+            col.push_many(&records).await?;
+        }
+        Ok(())
+    }
+
+    async fn load_heads(&mut self) -> TokioResult<()> {
+        let mut col = self.col.lock().await;
+        let size = col.size().await?;
+        let mut offset = 0;
+        while offset < size {
+            let count = std::cmp::min(READ_CHUNK_SIZE, size - offset);
+            let records = col.get_many(offset, count).await?;
+            for (ix, record) in records.iter().enumerate() {
+                self.heads.insert(record.wallet.clone(), (offset + ix) as u64);
+            }
+            offset += count;
+        }
+        Ok(())
+    }
+
+    /// Index the blocks after the last indexed one up to `bix_to`.
+    async fn index(&mut self, bix_to: u64, blockchain: &Blockchain,
+                   schema: &Schema,
+                   snapshots: &Snapshots) -> TokioResult<()> {
+        let mut state = if self.meta.bix > 0 {
+            build_state_at(self.meta.bix, blockchain, schema, snapshots).await?
+        } else {
+            State::new()
+        };
+
+        let mut bix = self.meta.bix + 1;
+        while bix <= bix_to {
+            let count = std::cmp::min(INDEX_CHUNK_SIZE, bix_to + 1 - bix);
+            for block_data in blockchain.get_block_data_many(bix, count)
+                                        .await?.iter() {
+                let senders = Transaction::calc_senders(
+                    &block_data.transactions, &state, schema
+                );
+                let moves = coin_moves(&block_data.block,
+                                       &block_data.transactions, &senders,
+                                       &state);
+                self.append(block_data.bix, &moves).await?;
+                state.roll_up(block_data.bix, &block_data.block,
+                              &block_data.transactions, schema);
+                self.meta = HistoryMeta {
+                    bix: block_data.bix, hash: block_data.block.hash.clone()
+                };
+            }
+            bix += count;
+            info!("History is indexed up to bix = {} of {}", bix - 1, bix_to);
+        }
+
+        Ok(())
+    }
+
+    async fn dump_meta(&self) -> TokioResult<()> {
+        let content = serde_json::to_vec(&self.meta)?;
+        write_atomic(&self.meta_path, &content).await
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use uqoin_core::block::Block;
+
+    use super::*;
+
+    fn coin_move(wallet: u64, coin: u64, tix: u64,
+                 is_incoming: bool) -> CoinMove {
+        CoinMove { wallet: U256::from(wallet), coin: U256::from(coin),
+                   order: 1, tix, is_incoming }
+    }
+
+    #[tokio::test]
+    async fn test_history() -> TokioResult<()> {
+        let path = std::env::temp_dir()
+            .join(format!("uqoin-history-{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        tokio::fs::create_dir_all(path).await?;
+
+        // Blockchain of two empty blocks
+        let blockchain = Blockchain::new(path).await?;
+        let (hash1, hash2) = (U256::from(1), U256::from(2));
+        blockchain.push_new_block(&Block::new(
+            0, 0, BlockInfo::genesis().hash, U256::from(0), U256::from(0),
+            hash1.clone()
+        ), &[]).await?;
+        blockchain.push_new_block(&Block::new(
+            0, 0, hash1.clone(), U256::from(0), U256::from(0), hash2.clone()
+        ), &[]).await?;
+
+        let (wallet_a, wallet_b) = (U256::from(10), U256::from(11));
+        let mut history = History::new(path).await?;
+        history.push(1, &hash1, &[coin_move(10, 100, 1, true),
+                                  coin_move(11, 101, 2, true)]).await?;
+        history.push(2, &hash2, &[coin_move(10, 100, 3, false),
+                                  coin_move(11, 100, 3, true)]).await?;
+
+        // Newest items go first
+        let (items, cursor) = history.get(&wallet_a, None, 10).await?
+                                     .unwrap();
+        assert_eq!(items.len(), 2);
+        assert_eq!((items[0].bix, items[0].direction), (2, Direction::Out));
+        assert_eq!((items[1].bix, items[1].direction), (1, Direction::In));
+        assert!(cursor.is_none());
+
+        // Pagination
+        let (items, cursor) = history.get(&wallet_b, None, 1).await?
+                                     .unwrap();
+        assert_eq!(items[0].tix, 3);
+        let (items, cursor_next) = history.get(&wallet_b, cursor, 1).await?
+                                          .unwrap();
+        assert_eq!(items[0].coin, U256::from(101));
+        assert!(cursor_next.is_none());
+        assert!(history.get(&wallet_a, cursor, 1).await?.is_none());
+
+        // Truncation restores the previous records of the wallets
+        history.truncate(1, &blockchain).await?;
+        let (items, _) = history.get(&wallet_a, None, 10).await?.unwrap();
+        assert_eq!(items.len(), 1);
+
+        // Reloading indexes the rest of the blockchain
+        let mut history = History::new(path).await?;
+        let snapshots = Snapshots::new(format!("{}/snapshots", path), 0, 1);
+        history.load(&blockchain, &Schema::new(), &snapshots).await?;
+        assert_eq!(history.meta.bix, 2);
+        let (items, _) = history.get(&wallet_b, None, 10).await?.unwrap();
+        assert_eq!(items.len(), 1);
+
+        tokio::fs::remove_dir_all(path).await
+    }
+}
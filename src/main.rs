@@ -1,21 +1,45 @@
+// The baseline tests compare unsigned numbers with zero
+#![cfg_attr(test, allow(clippy::absurd_extreme_comparisons))]
+
+mod cli;
 mod error;
+mod auth;
 mod utils;
 mod config;
+mod pool;
+mod peers;
+mod chain;
+mod snapshots;
+mod history;
+mod ratelimit;
+mod metrics;
+mod mining;
+mod events;
+mod remote;
+mod gossip;
 mod appdata;
 mod scopes;
 mod tasks;
+mod commands;
 
-use log::{info, error};
-use serde::Serialize;
+use log::{info, warn, error};
+use clap::Parser;
+use serde::{Serialize, Deserialize};
 use tokio::io::{Result as TokioResult};
+use tokio::signal::unix::{signal, SignalKind};
 use actix_web::{get, web, App, HttpResponse, HttpServer};
-use actix_web::middleware::Logger;
+use actix_web::middleware::{Logger, from_fn};
 use actix_web::http::header;
 use actix_cors::Cors;
 
 use crate::utils::*;
+use crate::cli::{Args, Command};
 use crate::config::Config;
 use crate::appdata::AppData;
+use crate::chain::dump_state;
+use crate::error::bad_request;
+use crate::ratelimit::rate_limit;
+use crate::metrics::track_latency;
 use crate::scopes::*;
 use crate::tasks::*;
 
@@ -33,10 +57,34 @@ async fn version_view() -> HttpResponse {
 }
 
 
-async fn run_task<F>(task: F, appdata: WebAppData) -> 
-                     TokioResult<()> where 
+#[get("/metrics")]
+async fn metrics_view(appdata: WebAppData) -> HttpResponse {
+    let content = appdata.metrics.render(&appdata).await;
+    HttpResponse::Ok().content_type("text/plain; version=0.0.4")
+                      .body(content)
+}
+
+
+#[derive(Deserialize)]
+struct EventsQuery {
+    wallet: Option<String>,
+}
+
+
+#[get("/events")]
+async fn events_view(appdata: WebAppData, 
+                     query: web::Query<EventsQuery>) -> APIResult {
+    let wallet = query.wallet.as_deref()
+        .map(|wallet| parse_param("wallet", wallet)).transpose()?;
+    Ok(events::response(appdata, move |envelope| {
+        envelope.concerns(wallet.as_ref())
+    }))
+}
+
+
+async fn run_task<F>(task: F, appdata: WebAppData) where 
                         F: AsyncFn(WebAppData) -> TokioResult<()> {
-    loop {
+    while !appdata.is_shutting_down() {
         if let Err(err) = task(appdata.clone()).await {
             error!("{:?}", err);
             info!("Restarting task");
@@ -45,15 +93,62 @@ async fn run_task<F>(task: F, appdata: WebAppData) ->
 }
 
 
+/// Wait for SIGINT or SIGTERM.
+async fn wait_for_signal() -> TokioResult<()> {
+    let mut sigterm = signal(SignalKind::terminate())?;
+    tokio::select! {
+        result = tokio::signal::ctrl_c() => result,
+        _ = sigterm.recv() => Ok(()),
+    }
+}
+
+
 #[actix_web::main]
 async fn main() -> TokioResult<()> {
+    // Command line arguments
+    let args = Args::parse();
+
     // Config
-    let config = Config::from_env();
+    let config = match Config::load(args.config.as_deref()) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        },
+    };
+
+    // Print config if requested
+    if args.print_config {
+        print!("{}", config.to_toml());
+        return Ok(());
+    }
 
     // Initialize logging
     let env = env_logger::Env::new().filter_or("LOG_LEVEL", "info");
     env_logger::init_from_env(env);
+    for warning in config.warnings.iter() {
+        warn!("{}", warning);
+    }
+
+    // Execute the command
+    match args.command.unwrap_or(Command::Run) {
+        Command::Run => run(config).await,
+        Command::RebuildState => commands::rebuild_state(&config).await,
+        Command::Verify => commands::verify(&config).await,
+        Command::Export { from, to, output } => 
+            commands::export(&config, from, to, &output).await,
+        Command::Import { input } => 
+            commands::import(&config, &input).await,
+        Command::Keygen => {
+            commands::keygen();
+            Ok(())
+        },
+    }
+}
+
 
+/// Run the node: background tasks and API server.
+async fn run(config: Config) -> TokioResult<()> {
     // Run options
     let workers = config.workers;
     let host = config.host.clone();
@@ -64,10 +159,16 @@ async fn main() -> TokioResult<()> {
     let appdata = web::Data::new(instance);
 
     // Background tasks
+    let mut tasks = Vec::new();
     if !appdata.config.lite_mode {
-        actix_web::rt::spawn(run_task(mine_task, appdata.clone()));
+        tasks.push(actix_web::rt::spawn(run_task(mine_task, appdata.clone())));
     }
-    actix_web::rt::spawn(run_task(sync_task, appdata.clone()));
+    tasks.push(actix_web::rt::spawn(run_task(sync_task, appdata.clone())));
+    tasks.push(actix_web::rt::spawn(run_task(discover_task, appdata.clone())));
+    tasks.push(actix_web::rt::spawn(run_task(persist_task, appdata.clone())));
+
+    // Keep appdata to flush it after the server is stopped
+    let appdata_server = appdata.clone();
 
     // Create API server
     let server = HttpServer::new(move || {
@@ -77,18 +178,53 @@ async fn main() -> TokioResult<()> {
             .allowed_header(header::CONTENT_TYPE);
 
         App::new()
+            .wrap(from_fn(rate_limit))
+            .wrap(from_fn(track_latency))
             .wrap(Logger::default())
             .wrap(cors)
-            .app_data(appdata.clone())
+            .app_data(appdata_server.clone())
+            .app_data(web::QueryConfig::default().error_handler(bad_request))
+            .app_data(web::JsonConfig::default().error_handler(bad_request))
             .service(version_view)
+            .service(metrics_view)
+            .service(events_view)
             .service(load_scope_coin())
             .service(load_scope_client())
             .service(load_scope_blockchain())
             .service(load_scope_node())
+            .service(load_scope_pool())
+            .service(load_scope_admin())
     })
         .workers(workers)
-        .bind((host, port))?;
+        .disable_signals()
+        .bind((host, port))?
+        .run();
+
+    // Stop accepting groups and the server on a signal
+    let server_handle = server.handle();
+    let appdata_signal = appdata.clone();
+    actix_web::rt::spawn(async move {
+        if let Err(err) = wait_for_signal().await {
+            error!("{:?}", err);
+        }
+        info!("Shutting down");
+        appdata_signal.request_shutdown();
+        server_handle.stop(true).await;
+    });
 
     // Run API server
-    server.run().await
+    server.await?;
+
+    // Let the background tasks finish their current steps
+    for task in tasks.into_iter() {
+        task.await?;
+    }
+
+    // Flush state and pool
+    let state = appdata.state.read().await;
+    dump_state(&appdata.config.get_state_path(), &state).await?;
+    appdata.pool.read().await.dump(&appdata.config.get_pool_path()).await?;
+    info!("Shutdown is complete");
+
+    Ok(())
 }
@@ -0,0 +1,238 @@
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::collections::BTreeMap;
+
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use tokio::time::Instant;
+
+use crate::utils::*;
+
+
+/// Buckets of the reorg depth histogram (in blocks).
+const REORG_DEPTH_BUCKETS: &[f64] = &[1.0, 2.0, 5.0, 10.0, 100.0, 1000.0];
+
+/// Buckets of the request latency histogram (in seconds).
+const LATENCY_BUCKETS: &[f64] = &[0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0];
+
+
+/// Histogram with cumulative buckets.
+#[derive(Debug, Clone)]
+struct Histogram {
+    bounds: &'static [f64],
+    counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+
+impl Histogram {
+    fn new(bounds: &'static [f64]) -> Self {
+        Self { bounds, counts: vec![0; bounds.len()], sum: 0.0, count: 0 }
+    }
+
+    fn observe(&mut self, value: f64) {
+        for (bound, count) in self.bounds.iter().zip(self.counts.iter_mut()) {
+            if value <= *bound {
+                *count += 1;
+            }
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+
+    fn render(&self, out: &mut String, name: &str, labels: &str) {
+        let sep = if labels.is_empty() { "" } else { "," };
+        for (bound, count) in self.bounds.iter().zip(self.counts.iter()) {
+            writeln!(out, "{}_bucket{{{}{}le=\"{}\"}} {}", 
+                     name, labels, sep, bound, count).unwrap();
+        }
+        writeln!(out, "{}_bucket{{{}{}le=\"+Inf\"}} {}", 
+                 name, labels, sep, self.count).unwrap();
+        let labels = if labels.is_empty() { 
+            String::new() 
+        } else { 
+            format!("{{{}}}", labels) 
+        };
+        writeln!(out, "{}_sum{} {}", name, labels, self.sum).unwrap();
+        writeln!(out, "{}_count{} {}", name, labels, self.count).unwrap();
+    }
+}
+
+
+/// Sync counters of a peer.
+#[derive(Debug, Clone, Default)]
+struct SyncCounters {
+    attempts: u64,
+    successes: u64,
+    failures: u64,
+}
+
+
+/// Node metrics collected at runtime. Gauges taken from the state, pool etc
+/// are added on rendering.
+#[derive(Debug)]
+pub struct Metrics {
+    sync: Mutex<BTreeMap<String, SyncCounters>>,
+    reorg_depth: Mutex<Histogram>,
+    requests: Mutex<BTreeMap<String, Histogram>>,
+}
+
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self {
+            sync: Mutex::new(BTreeMap::new()),
+            reorg_depth: Mutex::new(Histogram::new(REORG_DEPTH_BUCKETS)),
+            requests: Mutex::new(BTreeMap::new()),
+        }
+    }
+}
+
+
+impl Metrics {
+    /// Register the start of the sync with the node.
+    pub fn sync_started(&self, node: &str) {
+        self.sync.lock().unwrap().entry(node.to_string()).or_default()
+            .attempts += 1;
+    }
+
+    /// Register the result of the sync with the node.
+    pub fn sync_finished(&self, node: &str, success: bool) {
+        let mut sync = self.sync.lock().unwrap();
+        let counters = sync.entry(node.to_string()).or_default();
+        if success {
+            counters.successes += 1;
+        } else {
+            counters.failures += 1;
+        }
+    }
+
+    /// Register the reorg replacing `depth` local blocks.
+    pub fn reorg(&self, depth: u64) {
+        self.reorg_depth.lock().unwrap().observe(depth as f64);
+    }
+
+    /// Register the request to the route that took `seconds`.
+    pub fn request_finished(&self, route: &str, seconds: f64) {
+        self.requests.lock().unwrap().entry(route.to_string())
+            .or_insert_with(|| Histogram::new(LATENCY_BUCKETS))
+            .observe(seconds);
+    }
+
+    /// Render the metrics in Prometheus text format.
+    pub async fn render(&self, appdata: &WebAppData) -> String {
+        let mut out = String::new();
+
+        let block_info = appdata.state.read().await.get_last_block_info()
+                                .clone();
+        let pool_size = appdata.pool.read().await.len();
+        let is_syncing = *appdata.is_syncing.read().await;
+
+        gauge(&mut out, "uqoin_block_height", "Number of the last block.", 
+              block_info.bix);
+        gauge(&mut out, "uqoin_block_offset", 
+              "Number of transactions up to the last block.", 
+              block_info.offset);
+        gauge(&mut out, "uqoin_pool_size", "Number of pending groups.", 
+              pool_size as u64);
+        gauge(&mut out, "uqoin_syncing", "Whether the node is syncing.", 
+              is_syncing as u64);
+
+        header(&mut out, "uqoin_sync_attempts_total", "counter", 
+               "Sync attempts per peer.");
+        header(&mut out, "uqoin_sync_successes_total", "counter", 
+               "Successful syncs per peer.");
+        header(&mut out, "uqoin_sync_failures_total", "counter", 
+               "Failed syncs per peer.");
+        for (node, counters) in self.sync.lock().unwrap().iter() {
+            writeln!(out, "uqoin_sync_attempts_total{{peer=\"{}\"}} {}", 
+                     node, counters.attempts).unwrap();
+            writeln!(out, "uqoin_sync_successes_total{{peer=\"{}\"}} {}", 
+                     node, counters.successes).unwrap();
+            writeln!(out, "uqoin_sync_failures_total{{peer=\"{}\"}} {}", 
+                     node, counters.failures).unwrap();
+        }
+
+        header(&mut out, "uqoin_reorg_depth", "histogram", 
+               "Number of local blocks replaced in reorgs.");
+        self.reorg_depth.lock().unwrap()
+            .render(&mut out, "uqoin_reorg_depth", "");
+
+        header(&mut out, "uqoin_mined_blocks_total", "counter", 
+               "Blocks mined by this node since start (with replaced ones).");
+        writeln!(out, "uqoin_mined_blocks_total {}", 
+                 appdata.mining.get_mined_since_start()).unwrap();
+        header(&mut out, "uqoin_mining_nonces_total", "counter", 
+               "Nonces tried by the mining threads.");
+        writeln!(out, "uqoin_mining_nonces_total {}", 
+                 appdata.mining.get_nonces()).unwrap();
+        gauge(&mut out, "uqoin_mining_hashrate", 
+              "Nonces tried per second.", 
+              appdata.mining.get_info().hashrate);
+
+        header(&mut out, "uqoin_http_request_duration_seconds", "histogram", 
+               "HTTP request latency per route.");
+        for (route, histogram) in self.requests.lock().unwrap().iter() {
+            histogram.render(&mut out, "uqoin_http_request_duration_seconds", 
+                             &format!("route=\"{}\"", route));
+        }
+
+        out
+    }
+}
+
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    writeln!(out, "# HELP {} {}", name, help).unwrap();
+    writeln!(out, "# TYPE {} {}", name, kind).unwrap();
+}
+
+
+fn gauge(out: &mut String, name: &str, help: &str, value: u64) {
+    header(out, name, "gauge", help);
+    writeln!(out, "{} {}", name, value).unwrap();
+}
+
+
+/// Middleware that measures the latency of the requests per route.
+pub async fn track_latency(req: ServiceRequest, 
+                           next: Next<impl MessageBody>) -> 
+                           Result<ServiceResponse<impl MessageBody>, 
+                                  actix_web::Error> {
+    let instant = Instant::now();
+    let appdata = req.app_data::<WebAppData>().cloned();
+    let result = next.call(req).await;
+    if let Some(appdata) = appdata {
+        // Route is known after the request is processed
+        let route = result.as_ref().ok()
+            .and_then(|res| res.request().match_pattern())
+            .unwrap_or("unmatched".to_string());
+        appdata.metrics.request_finished(&route, 
+                                         instant.elapsed().as_secs_f64());
+    }
+    result
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_histogram() {
+        let mut histogram = Histogram::new(&[1.0, 10.0]);
+        histogram.observe(0.5);
+        histogram.observe(5.0);
+        histogram.observe(50.0);
+
+        let mut out = String::new();
+        histogram.render(&mut out, "depth", "");
+        assert_eq!(out, "depth_bucket{le=\"1\"} 1\n\
+                         depth_bucket{le=\"10\"} 2\n\
+                         depth_bucket{le=\"+Inf\"} 3\n\
+                         depth_sum 55.5\n\
+                         depth_count 3\n");
+    }
+}
@@ -0,0 +1,198 @@
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+
+use serde::Serialize;
+use uqoin_core::utils::U256;
+
+use crate::utils::*;
+
+
+/// Block being mined.
+#[derive(Debug, Clone, Serialize)]
+pub struct MiningTarget {
+    /// Hash of the previous block.
+    pub block_hash: U256,
+
+    /// Number of transactions in the block.
+    pub transactions: usize,
+}
+
+
+/// Mining status reported by the API.
+#[derive(Debug, Serialize)]
+pub struct MiningInfo {
+    /// Whether the mining task is running.
+    pub active: bool,
+
+    /// Whether mining is paused by the operator.
+    pub paused: bool,
+
+    /// Requested number of threads.
+    pub thread_count: usize,
+
+    /// Total hashrate (nonces per second).
+    pub hashrate: u64,
+
+    /// Hashrates of the threads.
+    pub thread_hashrates: Vec<u64>,
+
+    /// Block being mined.
+    pub target: Option<MiningTarget>,
+
+    /// Time since the last mined block (in milliseconds).
+    pub last_mined_ago: Option<u64>,
+
+    /// Number of blocks mined since the node started. It is not derived from
+    /// the blockchain, so the blocks replaced in reorgs are counted too.
+    pub mined_since_start: u64,
+}
+
+
+/// Nonce counter of a mining thread.
+#[derive(Debug)]
+struct ThreadStats {
+    nonces: Arc<AtomicU64>,
+    nonces_prev: u64,
+    hashrate: u64,
+}
+
+
+/// Mining statistics and controls shared between the mining task, its threads
+/// and the API.
+#[derive(Debug, Default)]
+pub struct Mining {
+    active: AtomicBool,
+    paused: AtomicBool,
+    thread_count: AtomicUsize,
+    threads: Mutex<Vec<ThreadStats>>,
+    nonces_finished: AtomicU64,
+    target: Mutex<Option<MiningTarget>>,
+    mined_since_start: AtomicU64,
+    last_mined: AtomicU64,
+}
+
+
+impl Mining {
+    /// Create mining controls with `thread_count` threads requested.
+    pub fn new(thread_count: usize) -> Self {
+        Self { thread_count: AtomicUsize::new(thread_count), ..Self::default() }
+    }
+
+    /// Mark mining as active.
+    pub fn start(&self) {
+        self.active.store(true, Ordering::Relaxed);
+    }
+
+    /// Mark mining as stopped. Nonces of the threads are kept in the total.
+    pub fn stop(&self) {
+        self.active.store(false, Ordering::Relaxed);
+        self.clear_target();
+        while self.remove_thread() {}
+    }
+
+    /// Register a new thread. It returns the nonce counter for the thread.
+    pub fn add_thread(&self) -> Arc<AtomicU64> {
+        let nonces = Arc::new(AtomicU64::new(0));
+        self.threads.lock().unwrap().push(ThreadStats {
+            nonces: Arc::clone(&nonces), nonces_prev: 0, hashrate: 0,
+        });
+        nonces
+    }
+
+    /// Unregister the last thread keeping its nonces in the total. It returns
+    /// `false` if there are no threads.
+    pub fn remove_thread(&self) -> bool {
+        if let Some(thread) = self.threads.lock().unwrap().pop() {
+            self.nonces_finished.fetch_add(
+                thread.nonces.load(Ordering::Relaxed), Ordering::Relaxed
+            );
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Pause or resume mining.
+    pub fn set_paused(&self, paused: bool) {
+        self.paused.store(paused, Ordering::Relaxed);
+    }
+
+    /// Check if mining is paused.
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+
+    /// Set the requested number of threads.
+    pub fn set_thread_count(&self, thread_count: usize) {
+        self.thread_count.store(thread_count, Ordering::Relaxed);
+    }
+
+    /// Number of threads that should be running (zero if paused).
+    pub fn get_running_thread_count(&self) -> usize {
+        if self.is_paused() {
+            0
+        } else {
+            self.thread_count.load(Ordering::Relaxed)
+        }
+    }
+
+    /// Set the block being mined.
+    pub fn set_target(&self, block_hash: U256, transactions: usize) {
+        *self.target.lock().unwrap() = 
+            Some(MiningTarget { block_hash, transactions });
+    }
+
+    /// Clear the block being mined.
+    pub fn clear_target(&self) {
+        *self.target.lock().unwrap() = None;
+    }
+
+    /// Recalculate hashrates of the threads from the nonces tried during the
+    /// last `elapsed` seconds.
+    pub fn update_hashrates(&self, elapsed: f64) {
+        for thread in self.threads.lock().unwrap().iter_mut() {
+            let nonces = thread.nonces.load(Ordering::Relaxed);
+            thread.hashrate = 
+                ((nonces - thread.nonces_prev) as f64 / elapsed) as u64;
+            thread.nonces_prev = nonces;
+        }
+    }
+
+    /// Register the block mined by this node.
+    pub fn block_mined(&self) {
+        self.mined_since_start.fetch_add(1, Ordering::Relaxed);
+        self.last_mined.store(timestamp(), Ordering::Relaxed);
+    }
+
+    /// Total number of nonces tried since the node started.
+    pub fn get_nonces(&self) -> u64 {
+        self.nonces_finished.load(Ordering::Relaxed) + 
+            self.threads.lock().unwrap().iter()
+                .map(|thread| thread.nonces.load(Ordering::Relaxed))
+                .sum::<u64>()
+    }
+
+    /// Number of blocks mined since the node started (see
+    /// `MiningInfo::mined_since_start`).
+    pub fn get_mined_since_start(&self) -> u64 {
+        self.mined_since_start.load(Ordering::Relaxed)
+    }
+
+    /// Current mining status.
+    pub fn get_info(&self) -> MiningInfo {
+        let thread_hashrates: Vec<u64> = self.threads.lock().unwrap().iter()
+            .map(|thread| thread.hashrate).collect();
+        let last_mined = self.last_mined.load(Ordering::Relaxed);
+        MiningInfo {
+            active: self.active.load(Ordering::Relaxed),
+            paused: self.is_paused(),
+            thread_count: self.thread_count.load(Ordering::Relaxed),
+            hashrate: thread_hashrates.iter().sum(),
+            thread_hashrates,
+            target: self.target.lock().unwrap().clone(),
+            last_mined_ago: (last_mined > 0)
+                .then(|| timestamp().saturating_sub(last_mined)),
+            mined_since_start: self.get_mined_since_start(),
+        }
+    }
+}
@@ -0,0 +1,297 @@
+use std::net::IpAddr;
+use std::collections::{HashMap, HashSet};
+
+use rand::Rng;
+use rand::prelude::IndexedRandom;
+use serde::Serialize;
+
+use crate::utils::*;
+
+
+/// Health record of the node.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct PeerRecord {
+    /// Average latency of the requests (in milliseconds).
+    pub latency: Option<u64>,
+
+    /// Time of the last successful request (in milliseconds since epoch).
+    pub last_success: Option<u64>,
+
+    /// Number of failed requests in a row.
+    pub failures: u64,
+
+    /// Number of times the node served invalid blocks.
+    pub strikes: u64,
+
+    /// Time until the node is banned (in milliseconds since epoch).
+    pub banned_until: Option<u64>,
+}
+
+
+impl PeerRecord {
+    /// Check if the node is banned at the moment `now`.
+    pub fn is_banned(&self, now: u64) -> bool {
+        self.banned_until.is_some_and(|until| until > now)
+    }
+
+    /// Weight of the node to be chosen for sync. Banned nodes have zero
+    /// weight, slow and failing nodes are chosen less often.
+    pub fn weight(&self, now: u64) -> f64 {
+        if self.is_banned(now) {
+            0.0
+        } else {
+            let latency = self.latency.unwrap_or(0) as f64 / 1000.0;
+            1.0 / (1.0 + self.failures as f64)
+                / (1.0 + self.strikes as f64)
+                / (1.0 + latency)
+        }
+    }
+}
+
+
+/// Validate URL of the node and bring it to the canonical form (without the
+/// trailing slash). Only HTTP and HTTPS URLs without path are allowed.
+pub fn parse_node_url(url: &str) -> Option<String> {
+    let parsed = reqwest::Url::parse(url).ok()?;
+    let is_valid = (parsed.scheme() == "http" || parsed.scheme() == "https")
+        && parsed.host_str().is_some()
+        && parsed.path() == "/"
+        && parsed.query().is_none()
+        && parsed.fragment().is_none();
+    is_valid.then(|| url.trim_end_matches('/').to_string())
+}
+
+
+/// Resolve IPs of the nodes. The nodes that cannot be resolved are skipped.
+pub async fn resolve_nodes(nodes: &[String]) -> HashSet<IpAddr> {
+    let mut ips = HashSet::new();
+    for node in nodes.iter() {
+        let Ok(parsed) = reqwest::Url::parse(node) else {
+            continue;
+        };
+        if let Some(host) = parsed.host_str() && 
+                let Some(port) = parsed.port_or_known_default() && 
+                let Ok(addrs) = tokio::net::lookup_host((host, port)).await {
+            ips.extend(addrs.map(|addr| addr.ip()));
+        }
+    }
+    ips
+}
+
+
+/// Rules to ban nodes.
+#[derive(Debug, Clone)]
+pub struct BanPolicy {
+    /// Number of failed requests in a row to ban the node.
+    pub failures_max: u64,
+
+    /// Number of invalid blocks served to ban the node.
+    pub strikes_max: u64,
+
+    /// Ban duration (in milliseconds).
+    pub timeout: u64,
+}
+
+
+/// Known nodes with their health records.
+#[derive(Debug, Clone)]
+pub struct Peers {
+    nodes: Vec<String>,
+    records: HashMap<String, PeerRecord>,
+    policy: BanPolicy,
+}
+
+
+impl Peers {
+    /// Create peers from the list of nodes.
+    pub fn new(nodes: Vec<String>, policy: BanPolicy) -> Self {
+        let records = nodes.iter()
+            .map(|node| (node.clone(), PeerRecord::default())).collect();
+        Self { nodes, records, policy }
+    }
+
+    /// List of nodes.
+    pub fn list(&self) -> &[String] {
+        &self.nodes
+    }
+
+    /// Number of nodes.
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Check if the node is known.
+    pub fn contains(&self, node: &str) -> bool {
+        self.records.contains_key(node)
+    }
+
+    /// Add a new node. It returns `false` if the node is already known.
+    pub fn push(&mut self, node: String) -> bool {
+        if self.contains(&node) {
+            false
+        } else {
+            self.records.insert(node.clone(), PeerRecord::default());
+            self.nodes.push(node);
+            true
+        }
+    }
+
+    /// Remove the node. It returns `false` if the node is not known.
+    pub fn remove(&mut self, node: &str) -> bool {
+        if self.records.remove(node).is_some() {
+            self.nodes.retain(|n| n != node);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Load list of nodes from a file.
+    pub async fn load_list(path: &str) -> TokioResult<Vec<String>> {
+        let content = tokio::fs::read_to_string(path).await?;
+        let nodes = serde_json::from_str(&content)?;
+        Ok(nodes)
+    }
+
+    /// Dump list of nodes to a file.
+    pub async fn dump_list(&self, path: &str) -> TokioResult<()> {
+        let content = serde_json::to_string(&self.nodes)?;
+        write_atomic(path, content.as_bytes()).await
+    }
+
+    /// Get health record of the node.
+    pub fn get_record(&self, node: &str) -> Option<&PeerRecord> {
+        self.records.get(node)
+    }
+
+    /// List of nodes that are not banned.
+    pub fn list_active(&self) -> Vec<String> {
+        let now = timestamp();
+        self.nodes.iter().filter(|node| !self.records[*node].is_banned(now))
+                  .cloned().collect()
+    }
+
+    /// Choose a random node that is not banned according to the weights.
+    pub fn choose<R: Rng>(&self, rng: &mut R) -> Option<String> {
+        let now = timestamp();
+        self.nodes.choose_weighted(rng, |node| self.records[node].weight(now))
+                  .ok().cloned()
+    }
+
+    /// Register successful request to the node that took `latency`
+    /// milliseconds.
+    pub fn report_success(&mut self, node: &str, latency: u64) {
+        if let Some(record) = self.records.get_mut(node) {
+            record.latency = Some(match record.latency {
+                Some(avg) => (3 * avg + latency) / 4,
+                None => latency,
+            });
+            record.last_success = Some(timestamp());
+            record.failures = 0;
+        }
+    }
+
+    /// Register failed request to the node. The node is banned after
+    /// `failures_max` failures in a row.
+    pub fn report_failure(&mut self, node: &str) {
+        if let Some(record) = self.records.get_mut(node) {
+            record.failures += 1;
+            if record.failures >= self.policy.failures_max {
+                record.banned_until = Some(timestamp() + self.policy.timeout);
+                record.failures = 0;
+            }
+        }
+    }
+
+    /// Register invalid blocks served by the node. The node is banned after
+    /// `strikes_max` strikes.
+    pub fn report_invalid(&mut self, node: &str) {
+        if let Some(record) = self.records.get_mut(node) {
+            record.strikes += 1;
+            if record.strikes >= self.policy.strikes_max {
+                record.banned_until = Some(timestamp() + self.policy.timeout);
+                record.strikes = 0;
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_peers() -> Peers {
+        let policy = BanPolicy { failures_max: 3, strikes_max: 2,
+                                 timeout: 60000 };
+        Peers::new(vec!["http://a".to_string(), "http://b".to_string()],
+                   policy)
+    }
+
+    #[test]
+    fn test_ban_on_failures() {
+        let mut peers = create_peers();
+        let mut rng = rand::rng();
+
+        peers.report_failure("http://a");
+        peers.report_failure("http://a");
+        assert_eq!(peers.list_active().len(), 2);
+
+        peers.report_failure("http://a");
+        assert_eq!(peers.list_active(), vec!["http://b".to_string()]);
+
+        for _ in 0..10 {
+            assert_eq!(peers.choose(&mut rng).unwrap(), "http://b");
+        }
+    }
+
+    #[test]
+    fn test_ban_on_strikes() {
+        let mut peers = create_peers();
+        let mut rng = rand::rng();
+
+        peers.report_invalid("http://a");
+        peers.report_invalid("http://b");
+        peers.report_invalid("http://b");
+        assert_eq!(peers.list_active(), vec!["http://a".to_string()]);
+
+        peers.report_invalid("http://a");
+        assert!(peers.list_active().is_empty());
+        assert!(peers.choose(&mut rng).is_none());
+    }
+
+    #[test]
+    fn test_parse_node_url() {
+        assert_eq!(parse_node_url("http://1.2.3.4:5772/").as_deref(),
+                   Some("http://1.2.3.4:5772"));
+        assert_eq!(parse_node_url("https://node.org").as_deref(),
+                   Some("https://node.org"));
+        assert!(parse_node_url("ftp://node.org").is_none());
+        assert!(parse_node_url("http://node.org/path").is_none());
+        assert!(parse_node_url("http://node.org?a=1").is_none());
+        assert!(parse_node_url("node.org").is_none());
+    }
+
+    #[test]
+    fn test_success() {
+        let mut peers = create_peers();
+
+        peers.report_failure("http://a");
+        peers.report_success("http://a", 100);
+        peers.report_success("http://a", 200);
+
+        let record = peers.get_record("http://a").unwrap();
+        assert_eq!(record.failures, 0);
+        assert_eq!(record.latency, Some(125));
+        assert!(record.last_success.is_some());
+
+        assert!(!peers.push("http://a".to_string()));
+        assert!(peers.push("http://c".to_string()));
+        assert_eq!(peers.len(), 3);
+
+        assert!(peers.remove("http://b"));
+        assert!(!peers.remove("http://b"));
+        assert_eq!(peers.list(), &["http://a".to_string(),
+                                   "http://c".to_string()]);
+    }
+}
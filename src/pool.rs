@@ -0,0 +1,480 @@
+use std::collections::{HashMap, VecDeque};
+
+use rand::Rng;
+use serde::{Serialize, Deserialize};
+use uqoin_core::utils::U256;
+use uqoin_core::state::State;
+use uqoin_core::schema::Schema;
+use uqoin_core::block::Block;
+use uqoin_core::pool::Pool as CorePool;
+use uqoin_core::transaction::{Transaction, Group};
+
+use crate::utils::*;
+use crate::events::{Event, Envelope, Events, transaction_wallets};
+
+
+/// Maximum number of statuses kept for the groups that left the pool.
+const RECEIPTS_MAX: usize = 100000;
+
+
+/// Status of the group sent to the node.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "status", rename_all = "lowercase")]
+pub enum GroupStatus {
+    /// The group is waiting in the pool.
+    Pending,
+
+    /// The group is included into the block `bix`, `tix` is the number of its
+    /// leading transaction.
+    Confirmed { bix: u64, tix: u64 },
+
+    /// The group was removed from the pool without being confirmed.
+    Dropped,
+
+    /// The group is not known by the node.
+    Unknown,
+}
+
+
+/// Reason the group is not added to the pool.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PoolRejection {
+    /// The sender has too many pending groups.
+    SenderLimit,
+
+    /// The pool is full and the group does not pay more than the cheapest
+    /// pending ones.
+    PoolFull,
+}
+
+
+/// Pending group in the pool.
+#[derive(Debug, Clone)]
+pub struct PoolEntry {
+    /// Group of transactions.
+    pub group: Group,
+
+    /// Sender of the group.
+    pub sender: U256,
+
+    /// Order of the fee coin (0 if there is no fee).
+    pub fee_order: u64,
+
+    /// Time the group was added (in milliseconds since epoch).
+    pub created: u64,
+
+    /// Hash of the group that identifies it.
+    hash: U256,
+
+    /// Hashes of the transactions of the group.
+    transaction_hashes: Vec<U256>,
+}
+
+
+/// Pending group as it is saved to the pool file.
+#[derive(Serialize, Deserialize)]
+pub struct PoolRecord {
+    /// Transactions of the group.
+    pub transactions: Vec<Transaction>,
+
+    /// Time the group was added (in milliseconds since epoch).
+    pub created: u64,
+}
+
+
+impl PoolEntry {
+    /// Create an entry calculating the fee order in the given state.
+    pub fn new(group: Group, sender: U256, state: &State, created: u64) -> 
+               Self {
+        let fee_order = group.get_fee()
+            .map(|tr| tr.get_order(state, &sender)).unwrap_or(0);
+        let hash = group.get_hash();
+        let transaction_hashes = group.transactions().iter()
+            .map(|tr| tr.get_hash()).collect();
+        Self { group, sender, fee_order, created, hash, transaction_hashes }
+    }
+
+    /// Hash of the group that identifies it.
+    pub fn get_hash(&self) -> U256 {
+        self.hash.clone()
+    }
+
+    /// Check if the group contains the transaction with the hash.
+    pub fn has_transaction(&self, hash: &U256) -> bool {
+        self.transaction_hashes.contains(hash)
+    }
+
+    /// Check if the group contains the coin.
+    pub fn has_coin(&self, coin: &U256) -> bool {
+        self.group.transactions().iter().any(|tr| &tr.coin == coin)
+    }
+
+    /// Wallets of the sender and the receivers of the group.
+    pub fn wallets(&self) -> Vec<U256> {
+        transaction_wallets(self.group.transactions(), 
+                            std::slice::from_ref(&self.sender))
+    }
+}
+
+
+/// Pool of pending groups. It works the same way as `uqoin_core::pool::Pool`
+/// keeping the groups in the order of arrival, but the groups are accessible
+/// for inspection. Also the pool remembers the final statuses of the groups
+/// that left it (up to `RECEIPTS_MAX`). The number of the groups is limited
+/// by `groups_max` in total and by `sender_groups_max` for every sender.
+/// The pending groups are mirrored in `uqoin_core::pool::Pool` that is used
+/// for mining.
+#[derive(Debug, Clone)]
+pub struct Pool {
+    entries: Vec<PoolEntry>,
+    core: CorePool,
+    receipts: HashMap<U256, GroupStatus>,
+    receipts_order: VecDeque<U256>,
+    events: Option<Events>,
+    groups_max: usize,
+    sender_groups_max: usize,
+}
+
+
+impl Pool {
+    /// Create an empty pool with the limits.
+    pub fn new(groups_max: usize, sender_groups_max: usize) -> Self {
+        Self {
+            entries: Vec::new(),
+            core: CorePool::new(),
+            receipts: HashMap::new(),
+            receipts_order: VecDeque::new(),
+            events: None,
+            groups_max,
+            sender_groups_max,
+        }
+    }
+
+    /// Emit `pool_added` and `pool_removed` events to the channel.
+    pub fn set_events(&mut self, events: Events) {
+        self.events = Some(events);
+    }
+
+    /// Number of pending groups.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Pending entries in the order of arrival.
+    pub fn entries(&self) -> &[PoolEntry] {
+        &self.entries
+    }
+
+    /// Clear pool. All pending groups are considered as dropped.
+    pub fn clear(&mut self) {
+        for entry in std::mem::take(&mut self.entries).into_iter() {
+            self.set_receipt(&entry, GroupStatus::Dropped);
+        }
+        self.core.clear();
+    }
+
+    /// Add a new group. `sender` must correspond to the group sender. If the
+    /// pool is full, cheaper groups are evicted to make room for it.
+    pub fn add(&mut self, group: Group, sender: U256, 
+               state: &State) -> Result<(), PoolRejection> {
+        self.insert(PoolEntry::new(group, sender, state, timestamp()))
+    }
+
+    /// Update the pool according to the given state removing the groups that
+    /// became invalid. It recalculates senders, so it may take a while.
+    /// Confirmed groups must be removed by `confirm` before, otherwise they
+    /// will be considered as dropped.
+    pub fn update(&mut self, state: &State, schema: &Schema) {
+        let old_entries = std::mem::take(&mut self.entries);
+        for old_entry in old_entries.into_iter() {
+            let trs = old_entry.group.transactions();
+            let senders = Transaction::calc_senders(trs, state, schema);
+            if let Ok(group) = Group::new(trs.to_vec(), state, &senders) {
+                self.entries.push(PoolEntry::new(
+                    group, senders[0].clone(), state, old_entry.created
+                ));
+            } else {
+                self.set_receipt(&old_entry, GroupStatus::Dropped);
+            }
+        }
+        self.rebuild_core();
+    }
+
+    /// Remove the groups included into the block `bix` marking them as
+    /// confirmed.
+    pub fn confirm(&mut self, bix: u64, block: &Block, 
+                   transactions: &[Transaction]) {
+        // Map transaction hash - tix
+        let tix_map: HashMap<U256, u64> = transactions.iter().enumerate()
+            .map(|(ix, tr)| (tr.get_hash(), block.offset + ix as u64 + 1))
+            .collect();
+
+        let old_entries = std::mem::take(&mut self.entries);
+        for entry in old_entries.into_iter() {
+            if let Some(tix) = tix_map.get(&entry.hash) {
+                let status = GroupStatus::Confirmed { bix, tix: *tix };
+                self.set_receipt(&entry, status);
+            } else {
+                self.entries.push(entry);
+            }
+        }
+        self.rebuild_core();
+    }
+
+    /// Mark the groups confirmed after the block `bix` as dropped since the
+    /// blocks are replaced.
+    pub fn revert(&mut self, bix: u64) {
+        for status in self.receipts.values_mut() {
+            if matches!(status, GroupStatus::Confirmed { bix: b, .. } 
+                                if *b > bix) {
+                *status = GroupStatus::Dropped;
+            }
+        }
+    }
+
+    /// Drop the groups that are older than `age_max` milliseconds.
+    pub fn prune(&mut self, age_max: u64) {
+        let time_min = timestamp().saturating_sub(age_max);
+        let old_entries = std::mem::take(&mut self.entries);
+        for entry in old_entries.into_iter() {
+            if entry.created >= time_min {
+                self.entries.push(entry);
+            } else {
+                self.set_receipt(&entry, GroupStatus::Dropped);
+            }
+        }
+        self.rebuild_core();
+    }
+
+    /// Load pending groups from the file validating them against the state.
+    /// Invalid groups are skipped. It returns the number of groups in the
+    /// file.
+    pub async fn load(&mut self, path: &str, state: &State, 
+                      schema: &Schema) -> TokioResult<usize> {
+        let content = tokio::fs::read(path).await?;
+        let records: Vec<PoolRecord> = serde_json::from_slice(&content)?;
+        let count = records.len();
+        for record in records.into_iter() {
+            let senders = Transaction::calc_senders(&record.transactions, 
+                                                    state, schema);
+            if let Ok(group) = Group::new(record.transactions, state, &senders)
+                    && !self.contains(&group.get_hash()) {
+                let _ = self.insert(PoolEntry::new(
+                    group, senders[0].clone(), state, record.created
+                ));
+            }
+        }
+        Ok(count)
+    }
+
+    /// Save pending groups to the file.
+    pub async fn dump(&self, path: &str) -> TokioResult<()> {
+        let records: Vec<PoolRecord> = self.entries.iter()
+            .map(|entry| PoolRecord {
+                transactions: entry.group.transactions().to_vec(),
+                created: entry.created,
+            }).collect();
+        let content = serde_json::to_vec(&records)?;
+        write_atomic(path, &content).await
+    }
+
+    /// Get status of the group by its hash.
+    pub fn get_status(&self, hash: &U256) -> GroupStatus {
+        if self.contains(hash) {
+            GroupStatus::Pending
+        } else {
+            self.receipts.get(hash).cloned().unwrap_or(GroupStatus::Unknown)
+        }
+    }
+
+    /// Check if the group with the hash is pending.
+    pub fn contains(&self, hash: &U256) -> bool {
+        self.entries.iter().any(|entry| &entry.hash == hash)
+    }
+
+    /// Prepare transactions and senders for the next block (see
+    /// `uqoin_core::pool::Pool::prepare`).
+    pub fn prepare<R: Rng>(&self, rng: &mut R, state: &State, schema: &Schema,
+                           validator_key: &U256, groups_max: Option<usize>) ->
+                           (Vec<Transaction>, Vec<U256>) {
+        self.core.prepare(rng, state, schema, validator_key, groups_max)
+    }
+
+    /// Find pending entry containing the transaction with the hash.
+    pub fn find_by_transaction(&self, hash: &U256) -> Option<&PoolEntry> {
+        self.entries.iter().find(|entry| entry.has_transaction(hash))
+    }
+
+    /// Find pending entry containing the coin.
+    pub fn find_by_coin(&self, coin: &U256) -> Option<&PoolEntry> {
+        self.entries.iter().find(|entry| entry.has_coin(coin))
+    }
+
+    /// Find a coin of the group that is spent by another pending group. It
+    /// returns the coin and the entry of that group.
+    pub fn find_conflict(&self, group: &Group) -> Option<(U256, &PoolEntry)> {
+        let hash = group.get_hash();
+        group.transactions().iter().find_map(|tr| {
+            self.find_by_coin(&tr.coin)
+                .filter(|entry| entry.hash != hash)
+                .map(|entry| (tr.coin.clone(), entry))
+        })
+    }
+
+    /// Iterate pending entries of the sender.
+    pub fn iter_by_sender<'a>(&'a self, sender: &'a U256) ->
+                              impl Iterator<Item = &'a PoolEntry> {
+        self.entries.iter().filter(move |entry| &entry.sender == sender)
+    }
+
+    fn insert(&mut self, entry: PoolEntry) -> Result<(), PoolRejection> {
+        if self.iter_by_sender(&entry.sender).count() >= 
+                self.sender_groups_max {
+            return Err(PoolRejection::SenderLimit);
+        }
+        if !self.make_room(entry.fee_order) {
+            return Err(PoolRejection::PoolFull);
+        }
+        if let Some(events) = self.events.as_ref() {
+            events.emit(Envelope::new(Event::PoolAdded {
+                group: entry.get_hash(),
+                sender: entry.sender.clone(),
+                fee_order: entry.fee_order,
+            }, entry.wallets()));
+        }
+        self.core.add(entry.group.clone(), entry.sender.clone());
+        self.entries.push(entry);
+        Ok(())
+    }
+
+    /// Make room for a group with the fee `fee_order` evicting the newest of
+    /// the lowest-fee groups while the pool is full. Nothing is evicted if
+    /// there are not enough groups with a lower fee.
+    fn make_room(&mut self, fee_order: u64) -> bool {
+        let excess = (self.entries.len() + 1).saturating_sub(self.groups_max);
+        let cheaper = self.entries.iter()
+            .filter(|entry| entry.fee_order < fee_order).count();
+        if cheaper < excess {
+            return false;
+        }
+        for _ in 0..excess {
+            let ix = self.entries.iter().enumerate().rev()
+                .min_by_key(|(_, entry)| entry.fee_order)
+                .map(|(ix, _)| ix).unwrap();
+            let entry = self.entries.remove(ix);
+            self.set_receipt(&entry, GroupStatus::Dropped);
+        }
+        if excess > 0 {
+            self.rebuild_core();
+        }
+        true
+    }
+
+    /// Mirror the pending groups in the core pool after some of them are
+    /// removed.
+    fn rebuild_core(&mut self) {
+        self.core.clear();
+        for entry in self.entries.iter() {
+            self.core.add(entry.group.clone(), entry.sender.clone());
+        }
+    }
+
+    fn set_receipt(&mut self, entry: &PoolEntry, status: GroupStatus) {
+        let hash = entry.get_hash();
+        if let Some(events) = self.events.as_ref() {
+            events.emit(Envelope::new(Event::PoolRemoved {
+                group: hash.clone(),
+                status: status.clone(),
+            }, entry.wallets()));
+        }
+        if self.receipts.insert(hash.clone(), status).is_none() {
+            self.receipts_order.push_back(hash);
+        }
+        while self.receipts_order.len() > RECEIPTS_MAX {
+            if let Some(hash) = self.receipts_order.pop_front() {
+                self.receipts.remove(&hash);
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use uqoin_core::coin::coin_random;
+
+    use super::*;
+
+    fn create_group(coins: &[&U256], sender: &U256) -> Group {
+        // Transfer of the first coin paying the rest as fees
+        let transactions: Vec<Transaction> = coins.iter().enumerate()
+            .map(|(ix, coin)| {
+                let addr = U256::from(if ix == 0 { 100 } else { 0 });
+                Transaction::new((*coin).clone(), addr, U256::from(0), 
+                                 U256::from(0))
+            }).collect();
+        let senders = vec![sender.clone(); transactions.len()];
+        Group::new(transactions, &State::new(), &senders).unwrap()
+    }
+
+    #[test]
+    fn test_find_conflict() {
+        let mut rng = rand::rng();
+        let sender = U256::from_hex(&"ab".repeat(32));
+        let coins: Vec<U256> = (0..3)
+            .map(|_| coin_random(&mut rng, &sender)).collect();
+
+        let mut pool = Pool::new(10, 10);
+        let pending = create_group(&[&coins[0]], &sender);
+        pool.add(pending.clone(), sender.clone(), &State::new()).unwrap();
+
+        // The pending group does not conflict with itself
+        assert!(pool.find_conflict(&pending).is_none());
+
+        // Another group spending the pending coin as fee
+        let group = create_group(&[&coins[1], &coins[0]], &sender);
+        let (coin, entry) = pool.find_conflict(&group).unwrap();
+        assert_eq!(coin, coins[0]);
+        assert_eq!(entry.get_hash(), pending.get_hash());
+
+        // Group with other coins
+        let group = create_group(&[&coins[2]], &sender);
+        assert!(pool.find_conflict(&group).is_none());
+    }
+
+    #[test]
+    fn test_make_room() {
+        let mut rng = rand::rng();
+        let sender = U256::from_hex(&"ab".repeat(32));
+
+        // Pool over its limit with the fees 5, 1, 1, 2
+        let mut pool = Pool::new(3, 10);
+        for fee_order in [5, 1, 1, 2] {
+            let coin = coin_random(&mut rng, &sender);
+            let mut entry = PoolEntry::new(create_group(&[&coin], &sender), 
+                                           sender.clone(), &State::new(), 0);
+            entry.fee_order = fee_order;
+            pool.entries.push(entry);
+        }
+
+        // Nothing is evicted if there are not enough cheaper groups
+        assert!(!pool.make_room(1));
+        assert_eq!(pool.len(), 4);
+
+        // Both lowest-fee groups are evicted to fit the new one
+        assert!(pool.make_room(2));
+        let fees: Vec<u64> = pool.entries().iter()
+            .map(|entry| entry.fee_order).collect();
+        assert_eq!(fees, vec![5, 2]);
+
+        // The sender limit
+        let mut pool = Pool::new(3, 1);
+        let coins: Vec<U256> = (0..2)
+            .map(|_| coin_random(&mut rng, &sender)).collect();
+        pool.add(create_group(&[&coins[0]], &sender), sender.clone(), 
+                 &State::new()).unwrap();
+        assert_eq!(pool.add(create_group(&[&coins[1]], &sender), 
+                            sender.clone(), &State::new()),
+                   Err(PoolRejection::SenderLimit));
+    }
+}
@@ -0,0 +1,189 @@
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::collections::{HashMap, HashSet, BTreeSet};
+
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+
+use crate::utils::*;
+use crate::error::JsonError;
+
+
+/// Number of clients to start forgetting the least recently seen ones.
+const BUCKETS_MAX: usize = 10000;
+
+
+/// Token bucket of a client.
+#[derive(Debug, Clone)]
+struct Bucket {
+    tokens: f64,
+    updated: u64,
+}
+
+
+/// Buckets of the clients with the index by the last update time.
+#[derive(Debug, Default)]
+struct Buckets {
+    by_ip: HashMap<IpAddr, Bucket>,
+    by_time: BTreeSet<(u64, IpAddr)>,
+}
+
+
+/// Token-bucket rate limiter keyed by client IP. Every request takes the
+/// cost of its route from the bucket (`1` by default), the bucket is refilled
+/// continuously up to its capacity. Exempt IPs (of the known nodes) are not
+/// limited.
+#[derive(Debug)]
+pub struct RateLimiter {
+    capacity: u64,
+    refill: u64,
+    costs: HashMap<String, u64>,
+    buckets: Mutex<Buckets>,
+    exempt: Mutex<HashSet<IpAddr>>,
+}
+
+
+impl RateLimiter {
+    /// Create a limiter with buckets of `capacity` tokens refilled by
+    /// `refill` tokens per second. Zero capacity disables the limiter,
+    /// otherwise `refill` must be positive.
+    pub fn new(capacity: u64, refill: u64, costs: HashMap<String, u64>) -> 
+               Self {
+        Self { 
+            capacity, refill, costs, 
+            buckets: Mutex::new(Buckets::default()),
+            exempt: Mutex::new(HashSet::new()),
+        }
+    }
+
+    /// Replace the set of IPs that are not limited.
+    pub fn set_exempt(&self, ips: HashSet<IpAddr>) {
+        *self.exempt.lock().unwrap() = ips;
+    }
+
+    /// Take tokens for the request to `path` from the bucket of `ip`. If
+    /// there are not enough tokens, it returns the number of seconds to
+    /// retry after.
+    pub fn take(&self, ip: IpAddr, path: &str) -> Result<(), u64> {
+        let cost = self.costs.get(path).cloned().unwrap_or(1);
+        self.take_at(ip, cost, timestamp())
+    }
+
+    fn take_at(&self, ip: IpAddr, cost: u64, now: u64) -> Result<(), u64> {
+        if self.capacity == 0 || self.exempt.lock().unwrap().contains(&ip) {
+            return Ok(());
+        }
+
+        let capacity = self.capacity as f64;
+        let refill = self.refill as f64 / 1000.0;
+        let cost = std::cmp::min(cost, self.capacity) as f64;
+
+        let mut buckets = self.buckets.lock().unwrap();
+        let Buckets { by_ip, by_time } = &mut *buckets;
+
+        // Forget the least recently seen client to make room for a new one
+        if !by_ip.contains_key(&ip) && by_ip.len() >= BUCKETS_MAX && 
+                let Some((_, ip_old)) = by_time.pop_first() {
+            by_ip.remove(&ip_old);
+        }
+
+        let bucket = by_ip.entry(ip)
+            .or_insert(Bucket { tokens: capacity, updated: now });
+        bucket.tokens = f64::min(
+            capacity,
+            bucket.tokens + now.saturating_sub(bucket.updated) as f64 * refill
+        );
+        by_time.remove(&(bucket.updated, ip));
+        by_time.insert((now, ip));
+        bucket.updated = now;
+
+        if bucket.tokens >= cost {
+            bucket.tokens -= cost;
+            Ok(())
+        } else {
+            Err(((cost - bucket.tokens) / refill / 1000.0).ceil() as u64)
+        }
+    }
+}
+
+
+/// Middleware that rejects requests of the clients exceeded the rate limit
+/// with 429 and `Retry-After` header.
+pub async fn rate_limit(req: ServiceRequest, 
+                        next: Next<impl MessageBody>) -> 
+                        Result<ServiceResponse<impl MessageBody>, 
+                               actix_web::Error> {
+    if let Some(appdata) = req.app_data::<WebAppData>() && 
+            let Some(ip) = client_ip(&req, 
+                                     appdata.config.rate_limit_trust_proxy) && 
+            let Err(retry_after) = appdata.rate_limiter.take(ip, req.path()) {
+        return Err(JsonError::too_many_requests(retry_after).into());
+    }
+    next.call(req).await
+}
+
+
+/// IP of the client. Behind a trusted proxy it is the last address in
+/// `X-Forwarded-For` that is appended by the proxy.
+fn client_ip(req: &ServiceRequest, trust_proxy: bool) -> Option<IpAddr> {
+    let forwarded = trust_proxy.then(|| req.headers().get("X-Forwarded-For")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.rsplit(',').next())
+        .and_then(|value| value.trim().parse().ok())).flatten();
+    forwarded.or(req.peer_addr().map(|addr| addr.ip()))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rate_limiter() {
+        let costs = HashMap::from([("/heavy".to_string(), 5)]);
+        let limiter = RateLimiter::new(10, 2, costs);
+        let ip1: IpAddr = "1.2.3.4".parse().unwrap();
+        let ip2: IpAddr = "5.6.7.8".parse().unwrap();
+
+        // Capacity is spent
+        assert!(limiter.take_at(ip1, 5, 1000).is_ok());
+        assert!(limiter.take_at(ip1, 5, 1000).is_ok());
+        assert_eq!(limiter.take_at(ip1, 1, 1000), Err(1));
+        assert_eq!(limiter.take_at(ip1, 5, 1000), Err(3));
+
+        // Other clients are not affected
+        assert!(limiter.take_at(ip2, 10, 1000).is_ok());
+
+        // Refill
+        assert!(limiter.take_at(ip1, 1, 1500).is_ok());
+        assert!(limiter.take_at(ip1, 1, 1500).is_err());
+        assert!(limiter.take_at(ip1, 10, 100000).is_ok());
+
+        // Exempt clients
+        limiter.set_exempt(HashSet::from([ip1]));
+        assert!(limiter.take_at(ip1, 10, 100000).is_ok());
+        assert!(limiter.take_at(ip1, 10, 100000).is_ok());
+
+        // Disabled limiter
+        let limiter = RateLimiter::new(0, 0, HashMap::new());
+        assert!(limiter.take(ip1, "/heavy").is_ok());
+    }
+
+    #[test]
+    fn test_rate_limiter_eviction() {
+        let limiter = RateLimiter::new(10, 1, HashMap::new());
+        let ip1: IpAddr = "1.2.3.4".parse().unwrap();
+        assert!(limiter.take_at(ip1, 10, 0).is_ok());
+
+        // The least recently seen client is forgotten
+        for ix in 0..BUCKETS_MAX as u32 {
+            let ip = IpAddr::from(ix.to_be_bytes());
+            assert!(limiter.take_at(ip, 1, 1).is_ok());
+        }
+        let buckets = limiter.buckets.lock().unwrap();
+        assert_eq!(buckets.by_ip.len(), BUCKETS_MAX);
+        assert_eq!(buckets.by_time.len(), BUCKETS_MAX);
+        assert!(!buckets.by_ip.contains_key(&ip1));
+    }
+}
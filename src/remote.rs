@@ -0,0 +1,85 @@
+use log::info;
+use tokio::io::{Error, ErrorKind};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use reqwest::header::CONTENT_TYPE;
+
+use crate::utils::*;
+
+
+/// Timeout of requests to remote nodes (in seconds).
+const REQUEST_TIMEOUT: u64 = 5;
+
+
+/// Make GET request to the remote node and parse JSON response.
+pub async fn request_node<T: DeserializeOwned, Q: Serialize>(
+        node: &str, path: &str, qs: Option<Q>) -> TokioResult<T> {
+    let query = qs.map(|q| serde_qs::to_string(&q).unwrap());
+
+    let url = if let Some(query) = query {
+        format!("{}{}?{}", node, path, query)
+    } else {
+        format!("{}{}", node, path)
+    };
+
+    info!("External node request: {}", url);
+
+    let resp = build_client().get(&url).send().await
+        .map_err(|_| Error::new(ErrorKind::NotFound, url))?;
+
+    let content: String = resp.text().await
+        .map_err(|err| Error::new(ErrorKind::InvalidData, err))?;
+    let instance = serde_json::from_str::<T>(&content)?;
+    Ok(instance)
+}
+
+
+/// Make GET request to the remote node up to `attempts` times until it
+/// succeeds. It bails out as soon as the shutdown is signalled.
+pub async fn request_node_retry<T: DeserializeOwned, Q: Serialize>(
+        appdata: &WebAppData, attempts: usize, node: &str, path: &str, 
+        qs: Option<Q>) -> TokioResult<T> {
+    let mut res = Err(ErrorKind::Other.into());
+    for _ in 0..attempts {
+        res = tokio::select! {
+            res = request_node(node, path, qs.as_ref()) => res,
+            _ = appdata.shutdown_signalled() => return Err(
+                Error::new(ErrorKind::Interrupted, "Shutting down")
+            ),
+        };
+        if res.is_ok() {
+            break;
+        }
+    }
+    res
+}
+
+
+/// Make POST request with JSON body to the remote node. The response body is
+/// ignored, only the status is checked.
+pub async fn post_node<B: Serialize>(node: &str, path: &str, 
+                                     body: &B) -> TokioResult<()> {
+    let url = format!("{}{}", node, path);
+
+    info!("External node post: {}", url);
+
+    let resp = build_client().post(&url)
+        .header(CONTENT_TYPE, "application/json")
+        .body(serde_json::to_string(body)?)
+        .send().await
+        .map_err(|_| Error::new(ErrorKind::NotFound, url.clone()))?;
+
+    if resp.status().is_success() {
+        Ok(())
+    } else {
+        Err(Error::new(ErrorKind::InvalidData, 
+                       format!("{} responded {}", url, resp.status())))
+    }
+}
+
+
+fn build_client() -> reqwest::Client {
+    reqwest::Client::builder()
+        .timeout(std::time::Duration::from_secs(REQUEST_TIMEOUT))
+        .build().unwrap()
+}
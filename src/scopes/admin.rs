@@ -0,0 +1,293 @@
+use log::info;
+use serde::{Serialize, Deserialize};
+use actix_web::{web, HttpRequest, HttpResponse, Scope};
+use tokio::io::AsyncWriteExt;
+
+use crate::api_check;
+use crate::utils::*;
+use crate::auth::Admin;
+use crate::error::{JsonError, ErrorCode};
+use crate::events::{Event, Envelope};
+use crate::chain::{build_state_at, dump_state};
+use crate::peers::parse_node_url;
+use crate::tasks::discover::probe_node;
+
+
+#[derive(Deserialize)]
+struct SyncQuery {
+    node: String,
+}
+
+
+#[derive(Deserialize)]
+struct TruncateQuery {
+    bix: u64,
+}
+
+
+#[derive(Deserialize)]
+struct SyncingQuery {
+    value: String,
+}
+
+
+#[derive(Deserialize)]
+struct ThreadsQuery {
+    threads: usize,
+}
+
+
+#[derive(Deserialize)]
+struct NodeQuery {
+    node: String,
+    check: Option<bool>,
+}
+
+
+#[derive(Serialize)]
+struct AuditRecord<'a> {
+    time: u64,
+    auth: &'a str,
+    ip: Option<String>,
+    action: &'a str,
+    details: &'a str,
+    result: String,
+}
+
+
+#[derive(Serialize)]
+struct ActionResult {
+    action: String,
+    bix: u64,
+    pool_size: usize,
+    is_syncing: bool,
+    syncing_override: Option<bool>,
+}
+
+
+/// Sync with the known node immediately.
+async fn sync_view(admin: Admin, req: HttpRequest, appdata: WebAppData, 
+                   query: web::Query<SyncQuery>) -> APIResult {
+    let node = query.node.trim_end_matches('/').to_string();
+    api_check!(appdata.nodes.read().await.contains(&node), UnknownNode);
+    appdata.request_sync(node.clone()).await;
+    audit(&appdata, &req, &admin, "sync", &node, Ok(())).await?;
+    action_result(&appdata, "sync").await
+}
+
+
+/// Drop all pending groups.
+async fn pool_clear_view(admin: Admin, req: HttpRequest, 
+                         appdata: WebAppData) -> APIResult {
+    let mut pool = appdata.pool.write().await;
+    let details = format!("{} groups", pool.len());
+    pool.clear();
+    let result = pool.dump(&appdata.config.get_pool_path()).await;
+    drop(pool);
+    audit(&appdata, &req, &admin, "pool-clear", &details, result).await?;
+    action_result(&appdata, "pool-clear").await
+}
+
+
+/// Save the state to the file.
+async fn state_dump_view(admin: Admin, req: HttpRequest, 
+                         appdata: WebAppData) -> APIResult {
+    let state = appdata.state.read().await;
+    let details = format!("bix {}", state.get_last_block_info().bix);
+    let result = dump_state(&appdata.config.get_state_path(), &state).await;
+    drop(state);
+    audit(&appdata, &req, &admin, "state-dump", &details, result).await?;
+    action_result(&appdata, "state-dump").await
+}
+
+
+/// Truncate the blockchain to the block `bix` rebuilding the state. The
+/// groups of the removed blocks are not returned to the pool. A sync round in
+/// progress is finished before and no new one starts meanwhile.
+async fn truncate_view(admin: Admin, req: HttpRequest, appdata: WebAppData, 
+                       query: web::Query<TruncateQuery>) -> APIResult {
+    let _sync_guard = appdata.sync_lock.lock().await;
+    let blockchain = appdata.blockchain.write().await;
+    let mut state = appdata.state.write().await;
+    let mut pool = appdata.pool.write().await;
+
+    let block_count = blockchain.get_block_count().await?;
+    api_check!(query.bix < block_count, InvalidBix);
+    let details = format!("bix {} of {}", query.bix, block_count);
+
+    let result = async {
+        blockchain.truncate(query.bix).await?;
+        appdata.history.write().await.truncate(query.bix, &blockchain)
+               .await?;
+        appdata.snapshots.discard_after(query.bix).await?;
+        let old_tip = state.get_last_block_info().clone();
+        *state = build_state_at(query.bix, &blockchain, &appdata.schema, 
+                                &appdata.snapshots).await?;
+        appdata.events.emit(Envelope::global(Event::Reorg {
+            depth: old_tip.bix.saturating_sub(query.bix),
+            old_tip,
+            new_tip: state.get_last_block_info().clone(),
+        }));
+        pool.revert(query.bix);
+        pool.update(&state, &appdata.schema);
+        dump_state(&appdata.config.get_state_path(), &state).await
+    }.await;
+    drop((blockchain, state, pool));
+
+    audit(&appdata, &req, &admin, "truncate", &details, result).await?;
+    action_result(&appdata, "truncate").await
+}
+
+
+/// Force `is_syncing` to `true` or `false`, or let the sync task manage it
+/// again (`auto`).
+async fn syncing_view(admin: Admin, req: HttpRequest, appdata: WebAppData, 
+                      query: web::Query<SyncingQuery>) -> APIResult {
+    let value = match query.value.as_str() {
+        "true" => Some(true),
+        "false" => Some(false),
+        "auto" => None,
+        _ => return Err(JsonError::new(ErrorCode::InvalidValue)),
+    };
+    *appdata.syncing_override.write().await = value;
+    if let Some(value) = value {
+        appdata.set_syncing(value).await;
+    }
+    audit(&appdata, &req, &admin, "syncing", &query.value, Ok(())).await?;
+    action_result(&appdata, "syncing").await
+}
+
+
+/// Pause mining. The threads exit after their current iteration, the pool is
+/// kept.
+async fn mining_pause_view(admin: Admin, req: HttpRequest, 
+                           appdata: WebAppData) -> APIResult {
+    api_check!(!appdata.config.lite_mode, LiteMode);
+    appdata.mining.set_paused(true);
+    audit(&appdata, &req, &admin, "mining-pause", "", Ok(())).await?;
+    action_result(&appdata, "mining-pause").await
+}
+
+
+/// Resume mining.
+async fn mining_resume_view(admin: Admin, req: HttpRequest, 
+                            appdata: WebAppData) -> APIResult {
+    api_check!(!appdata.config.lite_mode, LiteMode);
+    appdata.mining.set_paused(false);
+    audit(&appdata, &req, &admin, "mining-resume", "", Ok(())).await?;
+    action_result(&appdata, "mining-resume").await
+}
+
+
+/// Change the number of mining threads. It cannot exceed the available
+/// parallelism of the machine.
+async fn mining_threads_view(admin: Admin, req: HttpRequest, 
+                             appdata: WebAppData, 
+                             query: web::Query<ThreadsQuery>) -> APIResult {
+    api_check!(!appdata.config.lite_mode, LiteMode);
+    let threads_max = std::thread::available_parallelism()
+        .map(|count| count.get()).unwrap_or(1);
+    api_check!(query.threads > 0 && query.threads <= threads_max, 
+               InvalidThreads);
+    appdata.mining.set_thread_count(query.threads);
+    audit(&appdata, &req, &admin, "mining-threads", 
+          &query.threads.to_string(), Ok(())).await?;
+    action_result(&appdata, "mining-threads").await
+}
+
+
+/// Add a node to sync with. Unless `check` is `false`, the node is probed to
+/// be reachable and to have the same first block.
+async fn node_add_view(admin: Admin, req: HttpRequest, appdata: WebAppData, 
+                       query: web::Query<NodeQuery>) -> APIResult {
+    let node = parse_node_url(&query.node)
+        .ok_or(JsonError::new(ErrorCode::InvalidNode))?;
+
+    if query.check.unwrap_or(true) {
+        api_check!(probe_node(&node, &appdata).await, UnreachableNode);
+    }
+
+    let mut nodes = appdata.nodes.write().await;
+    let result = if nodes.push(node.clone()) {
+        nodes.dump_list(&appdata.config.get_nodes_path()).await
+    } else {
+        Ok(())
+    };
+    drop(nodes);
+    appdata.refresh_exempt().await;
+
+    audit(&appdata, &req, &admin, "node-add", &node, result).await?;
+    action_result(&appdata, "node-add").await
+}
+
+
+/// Remove a node to sync with.
+async fn node_remove_view(admin: Admin, req: HttpRequest, appdata: WebAppData, 
+                          query: web::Query<NodeQuery>) -> APIResult {
+    let node = parse_node_url(&query.node)
+        .ok_or(JsonError::new(ErrorCode::InvalidNode))?;
+
+    let mut nodes = appdata.nodes.write().await;
+    let result = if nodes.remove(&node) {
+        nodes.dump_list(&appdata.config.get_nodes_path()).await
+    } else {
+        Ok(())
+    };
+    drop(nodes);
+    appdata.refresh_exempt().await;
+
+    audit(&appdata, &req, &admin, "node-remove", &node, result).await?;
+    action_result(&appdata, "node-remove").await
+}
+
+
+/// Log the performed operator action with its result and append it to the
+/// audit file. The result of the action is returned after that.
+async fn audit(appdata: &WebAppData, req: &HttpRequest, admin: &Admin, 
+               action: &str, details: &str, 
+               result: TokioResult<()>) -> TokioResult<()> {
+    let ip = req.peer_addr().map(|addr| addr.ip().to_string());
+    let result_str = match result.as_ref() {
+        Ok(()) => "ok".to_string(),
+        Err(err) => err.to_string(),
+    };
+    info!(target: "audit", "{} ({}) by {} from {}: {}", action, details, 
+          admin.0, ip.as_deref().unwrap_or("unknown"), result_str);
+
+    let record = AuditRecord { 
+        time: timestamp(), auth: admin.0, ip, action, details, 
+        result: result_str,
+    };
+    let mut line = serde_json::to_vec(&record)?;
+    line.push(b'\n');
+    tokio::fs::OpenOptions::new().create(true).append(true)
+        .open(appdata.config.get_audit_path()).await?
+        .write_all(&line).await?;
+    result
+}
+
+
+async fn action_result(appdata: &WebAppData, action: &str) -> APIResult {
+    Ok(HttpResponse::Ok().json(ActionResult {
+        action: action.to_string(),
+        bix: appdata.state.read().await.get_last_block_info().bix,
+        pool_size: appdata.pool.read().await.len(),
+        is_syncing: *appdata.is_syncing.read().await,
+        syncing_override: *appdata.syncing_override.read().await,
+    }))
+}
+
+
+pub fn load_scope() -> Scope {
+    web::scope("/admin")
+        .route("/sync", web::post().to(sync_view))
+        .route("/pool/clear", web::post().to(pool_clear_view))
+        .route("/state/dump", web::post().to(state_dump_view))
+        .route("/truncate", web::post().to(truncate_view))
+        .route("/syncing", web::post().to(syncing_view))
+        .route("/mining/pause", web::post().to(mining_pause_view))
+        .route("/mining/resume", web::post().to(mining_resume_view))
+        .route("/mining/threads", web::post().to(mining_threads_view))
+        .route("/node/add", web::post().to(node_add_view))
+        .route("/node/remove", web::post().to(node_remove_view))
+}
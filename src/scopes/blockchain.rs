@@ -2,6 +2,7 @@ use serde::{Serialize, Deserialize};
 use actix_web::{web, HttpResponse, Scope};
 use uqoin_core::block::{BlockInfo, BlockData};
 
+use crate::api_check;
 use crate::utils::*;
 
 
@@ -36,7 +37,9 @@ async fn block_info_view(appdata: WebAppData,
                          query: web::Query<BlockQuery>) -> APIResult {
     let blockchain = appdata.blockchain.read().await;
 
-    let bix = query.bix.unwrap_or(blockchain.get_block_count().await?);
+    let block_count = blockchain.get_block_count().await?;
+    let bix = query.bix.unwrap_or(block_count);
+    api_check!(bix <= block_count, NotFound);
 
     let block_info = if bix > 0 {
         let block = blockchain.get_block(bix).await?;
@@ -58,7 +61,9 @@ async fn block_data_view(appdata: WebAppData,
                          query: web::Query<BlockQuery>) -> APIResult {
     let blockchain = appdata.blockchain.read().await;
 
-    let bix = query.bix.unwrap_or(blockchain.get_block_count().await?);
+    let block_count = blockchain.get_block_count().await?;
+    let bix = query.bix.unwrap_or(block_count);
+    api_check!(bix <= block_count, NotFound);
 
     let block_data = if bix > 0 {
         let block = blockchain.get_block(bix).await?;
@@ -101,6 +106,9 @@ async fn block_many_view(appdata: WebAppData,
 async fn transaction_view(appdata: WebAppData, 
                           query: web::Query<TransactionQuery>) -> APIResult {
     let blockchain = appdata.blockchain.read().await;
+    api_check!(query.tix > 0 && 
+               query.tix <= blockchain.get_transaction_count().await?, 
+               NotFound);
     let transaction = blockchain.get_transaction(query.tix).await?;
     Ok(HttpResponse::Ok().json(transaction))
 }
@@ -1,13 +1,27 @@
 use std::collections::HashMap;
 
-use serde::Deserialize;
+use serde::{Serialize, Deserialize};
+use serde_json::json;
 use actix_web::{web, HttpResponse, Scope};
 use actix_web::http::header::ContentType;
 use uqoin_core::utils::*;
+use uqoin_core::coin::coin_symbol;
 use uqoin_core::transaction::{Type, Transaction, Group};
 
 use crate::api_check;
 use crate::utils::*;
+use crate::error::{JsonError, ErrorCode};
+use crate::pool::{GroupStatus, PoolRejection};
+use crate::gossip::relay_group;
+use crate::events::{self, Event};
+use crate::history::HistoryItem;
+
+
+/// Number of history items returned by default.
+const HISTORY_LIMIT_DEFAULT: usize = 100;
+
+/// Maximum number of history items returned at once.
+const HISTORY_LIMIT_MAX: usize = 1000;
 
 
 #[derive(Deserialize)]
@@ -17,6 +31,39 @@ struct CoinsQuery {
 }
 
 
+#[derive(Deserialize)]
+struct StatusQuery {
+    id: String,
+}
+
+
+#[derive(Deserialize)]
+struct SubscribeQuery {
+    wallet: String,
+}
+
+
+#[derive(Deserialize)]
+struct HistoryQuery {
+    wallet: String,
+    cursor: Option<u64>,
+    limit: Option<usize>,
+}
+
+
+#[derive(Serialize)]
+struct HistoryPage {
+    items: Vec<HistoryItem>,
+    cursor: Option<u64>,
+}
+
+
+#[derive(Serialize)]
+pub struct GroupId {
+    pub id: U256,
+}
+
+
 /// Get coins belonging to the wallet at the last block. If `order` is  
 /// specified, the result coins are represented as a list, else the full mapping
 /// order - coin list will be returned. It is recommended not to use the 
@@ -24,7 +71,7 @@ struct CoinsQuery {
 async fn coins_view(appdata: WebAppData, 
                     query: web::Query<CoinsQuery>) -> APIResult {
     // Check syncing
-    api_check!(!*appdata.is_syncing.read().await, "Syncing");
+    api_check!(!*appdata.is_syncing.read().await, Syncing);
 
     // Prepare wallet number
     let wallet = U256::from_hex(&query.wallet);
@@ -67,7 +114,7 @@ async fn coins_view(appdata: WebAppData,
 async fn coins_hash_view(appdata: WebAppData, 
                          query: web::Query<CoinsQuery>) -> APIResult {
     // Check syncing
-    api_check!(!*appdata.is_syncing.read().await, "Syncing");
+    api_check!(!*appdata.is_syncing.read().await, Syncing);
 
     // Prepare wallet number
     let wallet = U256::from_hex(&query.wallet);
@@ -98,14 +145,38 @@ async fn coins_hash_view(appdata: WebAppData,
 }
 
 
-/// Send transaction group.
+/// Send transaction group. The response contains the group identifier that
+/// can be used to track the group in `status_view`. New groups are relayed
+/// to the known nodes.
 async fn send_view(appdata: WebAppData, 
                    transactions: web::Json<Vec<Transaction>>) -> APIResult {
+    // Validate the group and insert it into pool
+    let (id, status) = accept_group(&appdata, transactions.to_vec()).await?;
+
+    // Relay the group if it has just been added
+    if status != GroupStatus::Pending {
+        relay_group(&appdata, transactions.into_inner(), 1);
+    }
+
+    // Return group identifier
+    Ok(HttpResponse::Ok().json(GroupId { id }))
+}
+
+
+/// Validate transactions as a group, check the fee and insert the group into
+/// pool if it is not there yet. It returns the group identifier and the
+/// status of the group before the call.
+pub async fn accept_group(appdata: &WebAppData, 
+                          transactions: Vec<Transaction>) -> 
+                          Result<(U256, GroupStatus), JsonError> {
+    // Check shutdown
+    api_check!(!appdata.is_shutting_down(), ShuttingDown);
+
     // Check syncing
-    api_check!(!*appdata.is_syncing.read().await, "Syncing");
+    api_check!(!*appdata.is_syncing.read().await, Syncing);
 
     // Check lite mode (if private key is not provided)
-    api_check!(!appdata.config.lite_mode, "LiteMode");
+    api_check!(!appdata.config.lite_mode, LiteMode);
 
     // Get state
     let state = appdata.state.read().await;
@@ -115,23 +186,89 @@ async fn send_view(appdata: WebAppData,
                                             &appdata.schema);
 
     // Try to create group from raw transactions
-    let group = Group::new(transactions.to_vec(), &state, &senders)?;
+    let group = Group::new(transactions, &state, &senders)?;
+
+    // Get client fee
+    let fee_order = group.get_fee()
+        .map(|tr| tr.get_order(&state, &senders[0])).unwrap_or(0);
 
     // Skip split transactions for fee check
     if (group.get_type() != Type::Split) || (!appdata.config.free_split) {
-        // Get client fee
-        let fee_order = group.get_fee()
-            .map(|tr| tr.get_order(&state, &senders[0])).unwrap_or(0);
-
         // Check fee
-        api_check!(fee_order >= appdata.config.fee_min_order, "Fee");
+        if fee_order < appdata.config.fee_min_order {
+            return Err(fee_error(ErrorCode::Fee, 
+                                 appdata.config.fee_min_order));
+        }
     }
 
-    // Insert the group into pool
-    appdata.pool.write().await.add(group, senders[0].clone());
+    // Insert the group into pool if it is not there yet
+    let id = group.get_hash();
+    let mut pool = appdata.pool.write().await;
+    let status = pool.get_status(&id);
+    if status != GroupStatus::Pending {
+        // Check coins are not spent by another pending group
+        if let Some((coin, entry)) = pool.find_conflict(&group) {
+            return Err(JsonError::new(ErrorCode::DoubleSpend)
+                .details(json!({ "coin": coin.to_hex(), 
+                                 "group": entry.get_hash().to_hex() })));
+        }
+
+        // Add the group evicting cheaper ones if the pool is full
+        match pool.add(group, senders[0].clone(), &state) {
+            Err(PoolRejection::SenderLimit) =>
+                return Err(JsonError::new(ErrorCode::SenderLimit)),
+            Err(PoolRejection::PoolFull) =>
+                return Err(fee_error(ErrorCode::PoolFull, fee_order + 1)),
+            Ok(()) => {},
+        }
+    }
+
+    Ok((id, status))
+}
+
+
+/// Error asking for a fee of the order `order` at least.
+fn fee_error(code: ErrorCode, order: u64) -> JsonError {
+    JsonError::new(code).details(json!({ "fee": coin_symbol(order) }))
+}
+
+
+/// Get status of the group by its identifier returned from `send_view`.
+async fn status_view(appdata: WebAppData, 
+                     query: web::Query<StatusQuery>) -> APIResult {
+    let id = parse_param("id", &query.id)?;
+    let status = appdata.pool.read().await.get_status(&id);
+    Ok(HttpResponse::Ok().json(status))
+}
+
+
+/// Get coin moves of the wallet from the newest ones. The response contains
+/// `cursor` to get the older items if there are any.
+async fn history_view(appdata: WebAppData, 
+                      query: web::Query<HistoryQuery>) -> APIResult {
+    let wallet = parse_param("wallet", &query.wallet)?;
+    let limit = query.limit.unwrap_or(HISTORY_LIMIT_DEFAULT);
+    api_check!(limit > 0 && limit <= HISTORY_LIMIT_MAX, InvalidValue);
+
+    let (items, cursor) = appdata.history.read().await
+        .get(&wallet, query.cursor, limit).await?
+        .ok_or(JsonError::new(ErrorCode::InvalidValue))?;
+
+    Ok(HttpResponse::Ok().json(HistoryPage { items, cursor }))
+}
+
 
-    // Ok
-    Ok(HttpResponse::Ok().finish())
+/// Stream changes of the wallet coins (`coins_changed` events) as Server-Sent
+/// Events. `reorg` events are streamed too, because the changes of blocks 
+/// removed by truncation or restored from a snapshot are not reported.
+async fn subscribe_view(appdata: WebAppData, 
+                        query: web::Query<SubscribeQuery>) -> APIResult {
+    let wallet = parse_param("wallet", &query.wallet)?;
+    Ok(events::response(appdata, move |envelope| match &envelope.event {
+        Event::CoinsChanged { wallet: w, .. } => w == &wallet,
+        Event::Reorg { .. } => true,
+        _ => false,
+    }))
 }
 
 
@@ -140,4 +277,7 @@ pub fn load_scope() -> Scope {
         .route("/coins", web::get().to(coins_view))
         .route("/coins/hash", web::get().to(coins_hash_view))
         .route("/send", web::post().to(send_view))
+        .route("/status", web::get().to(status_view))
+        .route("/subscribe", web::get().to(subscribe_view))
+        .route("/history", web::get().to(history_view))
 }
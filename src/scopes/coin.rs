@@ -1,10 +1,20 @@
-use serde::Deserialize;
+use serde::{Serialize, Deserialize};
 use actix_web::{web, HttpResponse, Scope};
 use actix_web::http::header::ContentType;
 use uqoin_core::utils::U256;
+use uqoin_core::transaction::Type;
 
 use crate::api_check;
 use crate::utils::*;
+use crate::error::{JsonError, ErrorCode};
+
+
+/// Number of blocks read at once while looking for the coin owner.
+const OWNER_CHUNK_SIZE: u64 = 100;
+
+/// Number of attempts to find the coin owner if the blockchain is replaced
+/// during the walk.
+const OWNER_WALK_ATTEMPTS: usize = 3;
 
 
 #[derive(Deserialize)]
@@ -13,10 +23,23 @@ struct Query {
 }
 
 
+#[derive(Deserialize)]
+struct OwnerQuery {
+    coin: String,
+    bix: Option<u64>,
+}
+
+
+#[derive(Serialize)]
+struct OwnerInfo {
+    wallet: U256,
+}
+
+
 /// Get coin info.
 async fn info_view(appdata: WebAppData, 
                    query: web::Query<Query>) -> APIResult {
-    api_check!(!*appdata.is_syncing.read().await, "Syncing");
+    api_check!(!*appdata.is_syncing.read().await, Syncing);
     let coin = U256::from_hex(&query.coin);
     let state = appdata.state.read().await;
     if let Some(coin_info) = state.get_coin_info(&coin) {
@@ -27,7 +50,104 @@ async fn info_view(appdata: WebAppData,
 }
 
 
+/// Get owner of the coin. If `bix` is specified, the owner at the moment of
+/// that block is returned, so the blockchain is walked down from `bix` until 
+/// the last transaction of the coin is found.
+async fn owner_view(appdata: WebAppData, 
+                    query: web::Query<OwnerQuery>) -> APIResult {
+    api_check!(!*appdata.is_syncing.read().await, Syncing);
+
+    // Prepare coin number
+    let coin = parse_param("coin", &query.coin)?;
+
+    // Get state and last bix
+    let state = appdata.state.read().await;
+    let bix_last = state.get_last_block_info().bix;
+    let bix = query.bix.unwrap_or(bix_last);
+    api_check!(bix <= bix_last, NotFound);
+
+    // Unknown coin has never been owned by anybody
+    let owner = if state.get_coin_info(&coin).is_none() {
+        None
+    } else if bix == bix_last {
+        // Take the current owner from the state
+        state.get_owner(&coin).cloned()
+    } else {
+        // Release the state and walk the blockchain history
+        drop(state);
+        find_owner_at(&appdata, &coin, bix).await?
+    };
+
+    if let Some(wallet) = owner {
+        Ok(HttpResponse::Ok().json(OwnerInfo { wallet }))
+    } else {
+        Ok(HttpResponse::Ok().insert_header(ContentType::json()).body("{}"))
+    }
+}
+
+
+/// Find the owner of the coin right after the block `bix`. The walk is
+/// restarted if the blockchain is replaced meanwhile.
+async fn find_owner_at(appdata: &WebAppData, coin: &U256, 
+                       bix: u64) -> Result<Option<U256>, JsonError> {
+    for _ in 0..OWNER_WALK_ATTEMPTS {
+        if let Some(owner) = walk_owner(appdata, coin, bix).await? {
+            return Ok(owner);
+        }
+    }
+    Err(JsonError::with_detail(ErrorCode::Syncing, 
+                               "The blockchain changed during the lookup"))
+}
+
+
+/// Look for the latest transaction of the coin in the blocks `bix`, 
+/// `bix - 1`, ..., `1`. The blocks are read by chunks releasing the blockchain
+/// in between, so a long walk does not block sync and mining. Every block must
+/// be the parent of the previously read one, otherwise the blockchain has been
+/// replaced and `None` is returned.
+async fn walk_owner(appdata: &WebAppData, coin: &U256, 
+                    bix: u64) -> TokioResult<Option<Option<U256>>> {
+    let mut bix_to = bix;
+    let mut hash_expected: Option<U256> = None;
+
+    while bix_to > 0 {
+        let bix_from = bix_to.saturating_sub(OWNER_CHUNK_SIZE - 1).max(1);
+        let blockchain = appdata.blockchain.read().await;
+        if blockchain.get_block_count().await? < bix_to {
+            return Ok(None);
+        }
+        let blocks = blockchain
+            .get_block_data_many(bix_from, bix_to + 1 - bix_from).await?;
+        drop(blockchain);
+
+        for block_data in blocks.iter().rev() {
+            if hash_expected.is_some_and(|hash| hash != block_data.block.hash) {
+                return Ok(None);
+            }
+            hash_expected = Some(block_data.block.hash_prev.clone());
+
+            // The last transaction of the coin in the block defines the owner
+            let tr_opt = block_data.transactions.iter().rev()
+                .find(|tr| &tr.coin == coin);
+            if let Some(tr) = tr_opt {
+                let owner = if tr.get_type() == Type::Transfer {
+                    tr.addr.clone()
+                } else {
+                    block_data.block.validator.clone()
+                };
+                return Ok(Some(Some(owner)));
+            }
+        }
+
+        bix_to = bix_from - 1;
+    }
+
+    Ok(Some(None))
+}
+
+
 pub fn load_scope() -> Scope {
     web::scope("/coin")
         .route("/info", web::get().to(info_view))
+        .route("/owner", web::get().to(owner_view))
 }
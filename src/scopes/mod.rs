@@ -2,8 +2,12 @@ pub mod coin;
 pub mod client;
 pub mod blockchain;
 pub mod node;
+pub mod pool;
+pub mod admin;
 
 pub use coin::{load_scope as load_scope_coin};
 pub use client::{load_scope as load_scope_client};
 pub use blockchain::{load_scope as load_scope_blockchain};
 pub use node::{load_scope as load_scope_node};
+pub use pool::{load_scope as load_scope_pool};
+pub use admin::{load_scope as load_scope_admin};
@@ -1,9 +1,31 @@
-use serde::Serialize;
+use serde::{Serialize, Deserialize};
 use actix_web::{web, HttpResponse, Scope};
 use uqoin_core::utils::U256;
 use uqoin_core::coin::coin_symbol;
+use uqoin_core::block::BlockInfo;
 
+use crate::api_check;
 use crate::utils::*;
+use crate::pool::GroupStatus;
+use crate::peers::PeerRecord;
+use crate::gossip::{RelayRequest, BlockAnnouncement, relay_group};
+use crate::tasks::sync::need_to_sync;
+use crate::scopes::client::{GroupId, accept_group};
+
+
+#[derive(Deserialize)]
+struct ListQuery {
+    extended: Option<bool>,
+}
+
+
+#[derive(Serialize)]
+struct NodeHealth {
+    node: String,
+    banned: bool,
+    #[serde(flatten)]
+    record: PeerRecord,
+}
 
 
 #[derive(Debug, Serialize)]
@@ -16,10 +38,22 @@ struct NodeInfo {
 }
 
 
-/// Get list of known nodes.
-async fn list_view(appdata: WebAppData) -> APIResult {
-    let nodes = appdata.nodes.read().await.clone();
-    Ok(HttpResponse::Ok().json(nodes))
+/// Get list of known nodes. If `extended` is set, the health records of the
+/// nodes are included.
+async fn list_view(appdata: WebAppData, 
+                   query: web::Query<ListQuery>) -> APIResult {
+    let nodes = appdata.nodes.read().await;
+    if query.extended.unwrap_or(false) {
+        let now = timestamp();
+        let node_infos: Vec<NodeHealth> = nodes.list().iter().map(|node| {
+            let record = nodes.get_record(node).unwrap().clone();
+            let banned = record.is_banned(now);
+            NodeHealth { node: node.clone(), banned, record }
+        }).collect();
+        Ok(HttpResponse::Ok().json(node_infos))
+    } else {
+        Ok(HttpResponse::Ok().json(nodes.list()))
+    }
 }
 
 
@@ -40,8 +74,61 @@ async fn info_view(appdata: WebAppData) -> APIResult {
 }
 
 
+/// Get mining status: hashrate, block being mined and mined blocks.
+async fn mining_view(appdata: WebAppData) -> APIResult {
+    Ok(HttpResponse::Ok().json(appdata.mining.get_info()))
+}
+
+
+/// Accept a group relayed by another node. If the group is new for this node,
+/// it is relayed further.
+async fn relay_view(appdata: WebAppData, 
+                    request: web::Json<RelayRequest>) -> APIResult {
+    let RelayRequest { transactions, hops } = request.into_inner();
+
+    // Validate the group and insert it into pool
+    let (id, status) = accept_group(&appdata, transactions.clone()).await?;
+
+    // Relay the group further if it has just been added
+    if status != GroupStatus::Pending {
+        relay_group(&appdata, transactions, hops + 1);
+    }
+
+    Ok(HttpResponse::Ok().json(GroupId { id }))
+}
+
+
+/// Accept announcement of a new block from a known node. If the announced block
+/// is better than the local last block, the sync task syncs with the node
+/// immediately.
+async fn announce_view(appdata: WebAppData, 
+                       announcement: web::Json<BlockAnnouncement>) -> 
+                       APIResult {
+    // Only known nodes are trusted to sync with
+    api_check!(appdata.nodes.read().await.contains(&announcement.node), 
+               UnknownNode);
+
+    let last_info_remote = BlockInfo {
+        bix: announcement.bix,
+        offset: announcement.offset,
+        hash: announcement.hash.clone(),
+    };
+    let last_info_local = appdata.state.read().await
+                                 .get_last_block_info().clone();
+
+    if need_to_sync(&last_info_remote, &last_info_local) {
+        appdata.request_sync(announcement.into_inner().node).await;
+    }
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+
 pub fn load_scope() -> Scope {
     web::scope("/node")
         .route("/list", web::get().to(list_view))
         .route("/info", web::get().to(info_view))
+        .route("/mining", web::get().to(mining_view))
+        .route("/relay", web::post().to(relay_view))
+        .route("/announce", web::post().to(announce_view))
 }
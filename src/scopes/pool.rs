@@ -0,0 +1,113 @@
+use serde::{Serialize, Deserialize};
+use actix_web::{web, HttpResponse, Scope};
+use actix_web::http::header::ContentType;
+use uqoin_core::utils::U256;
+use uqoin_core::coin::coin_symbol;
+use uqoin_core::transaction::Transaction;
+
+use crate::api_check;
+use crate::utils::*;
+use crate::pool::PoolEntry;
+
+
+#[derive(Deserialize)]
+struct GroupsQuery {
+    wallet: Option<String>,
+}
+
+
+#[derive(Deserialize)]
+struct LookupQuery {
+    coin: Option<String>,
+    hash: Option<String>,
+}
+
+
+#[derive(Serialize)]
+struct PoolSize {
+    size: usize,
+}
+
+
+#[derive(Serialize)]
+struct GroupInfo {
+    hash: U256,
+    #[serde(rename = "type")]
+    group_type: String,
+    sender: U256,
+    fee_order: u64,
+    fee: Option<String>,
+    transactions: Vec<Transaction>,
+}
+
+
+impl From<&PoolEntry> for GroupInfo {
+    fn from(entry: &PoolEntry) -> Self {
+        Self {
+            hash: entry.get_hash(),
+            group_type: format!("{:?}", entry.group.get_type()),
+            sender: entry.sender.clone(),
+            fee_order: entry.fee_order,
+            fee: (entry.fee_order > 0).then(|| coin_symbol(entry.fee_order)),
+            transactions: entry.group.transactions().to_vec(),
+        }
+    }
+}
+
+
+/// Get number of pending groups.
+async fn size_view(appdata: WebAppData) -> APIResult {
+    let size = appdata.pool.read().await.len();
+    Ok(HttpResponse::Ok().json(PoolSize { size }))
+}
+
+
+/// Get pending groups in the order of arrival. If `wallet` is specified, only
+/// the groups sent by the wallet are returned.
+async fn groups_view(appdata: WebAppData,
+                     query: web::Query<GroupsQuery>) -> APIResult {
+    let pool = appdata.pool.read().await;
+    let groups: Vec<GroupInfo> = if let Some(wallet) = query.wallet.as_ref() {
+        let wallet = parse_param("wallet", wallet)?;
+        pool.iter_by_sender(&wallet).map(GroupInfo::from).collect()
+    } else {
+        pool.entries().iter().map(GroupInfo::from).collect()
+    };
+    Ok(HttpResponse::Ok().json(groups))
+}
+
+
+/// Find pending group by `coin` or transaction `hash` (only one of them may
+/// be given). Empty object is returned if nothing is pending.
+async fn lookup_view(appdata: WebAppData,
+                     query: web::Query<LookupQuery>) -> APIResult {
+    api_check!(query.coin.is_some() || query.hash.is_some(), Query);
+    api_check!(query.coin.is_none() || query.hash.is_none(), InvalidValue);
+
+    let coin = query.coin.as_ref()
+        .map(|coin| parse_param("coin", coin)).transpose()?;
+    let hash = query.hash.as_ref()
+        .map(|hash| parse_param("hash", hash)).transpose()?;
+
+    let pool = appdata.pool.read().await;
+
+    let entry = if let Some(coin) = coin.as_ref() {
+        pool.find_by_coin(coin)
+    } else {
+        pool.find_by_transaction(hash.as_ref().unwrap())
+    };
+
+    if let Some(entry) = entry {
+        Ok(HttpResponse::Ok().json(GroupInfo::from(entry)))
+    } else {
+        Ok(HttpResponse::Ok().insert_header(ContentType::json()).body("{}"))
+    }
+}
+
+
+pub fn load_scope() -> Scope {
+    web::scope("/pool")
+        .route("/size", web::get().to(size_view))
+        .route("/groups", web::get().to(groups_view))
+        .route("/lookup", web::get().to(lookup_view))
+}
@@ -0,0 +1,96 @@
+use log::{info, warn};
+use lbasedb::path_concat;
+use uqoin_core::state::State;
+use uqoin_core::blockchain::Blockchain;
+
+use crate::utils::*;
+use crate::chain::{read_state, dump_state, is_state_on_chain};
+
+
+/// Rotating state snapshots stored as `state-<bix>.json` in the directory.
+#[derive(Debug, Clone)]
+pub struct Snapshots {
+    path: String,
+    interval: u64,
+    count_max: usize,
+}
+
+
+impl Snapshots {
+    /// Create snapshots in the directory `path` taken every `interval` blocks
+    /// (`0` disables them) keeping at most `count_max` latest ones.
+    pub fn new(path: String, interval: u64, count_max: usize) -> Self {
+        Self { path, interval, count_max }
+    }
+
+    /// Snapshot interval in blocks.
+    pub fn interval(&self) -> u64 {
+        self.interval
+    }
+
+    /// List block numbers of the snapshots in ascending order.
+    pub async fn list(&self) -> TokioResult<Vec<u64>> {
+        let mut bixs = Vec::new();
+        if let Ok(mut entries) = tokio::fs::read_dir(&self.path).await {
+            while let Some(entry) = entries.next_entry().await? {
+                if let Some(bix) = entry.file_name().to_str()
+                        .and_then(|name| name.strip_prefix("state-"))
+                        .and_then(|name| name.strip_suffix(".json"))
+                        .and_then(|bix| bix.parse().ok()) {
+                    bixs.push(bix);
+                }
+            }
+        }
+        bixs.sort();
+        Ok(bixs)
+    }
+
+    /// Save a snapshot of the state if the latest one is at least `interval`
+    /// blocks behind. The oldest snapshots over `count_max` are removed.
+    pub async fn save(&self, state: &State) -> TokioResult<()> {
+        let bix = state.get_last_block_info().bix;
+        let bixs = self.list().await?;
+        let bix_latest = bixs.last().cloned().unwrap_or(0);
+
+        if self.interval > 0 && bix >= bix_latest + self.interval {
+            tokio::fs::create_dir_all(&self.path).await?;
+            dump_state(&self.get_path(bix), state).await?;
+            info!("Snapshot saved, bix = {}", bix);
+
+            let count = bixs.len() + 1;
+            for bix in bixs.iter().take(count.saturating_sub(self.count_max)) {
+                tokio::fs::remove_file(self.get_path(*bix)).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Load the latest snapshot not after `bix_max` that matches the
+    /// blockchain. Corrupt snapshots are skipped.
+    pub async fn load_nearest(&self, bix_max: u64, blockchain: &Blockchain) -> 
+                              TokioResult<Option<State>> {
+        for bix in self.list().await?.into_iter().rev()
+                       .filter(|bix| *bix <= bix_max) {
+            match read_state(&self.get_path(bix)).await {
+                Ok(state) if is_state_on_chain(&state, blockchain).await? => {
+                    return Ok(Some(state));
+                },
+                _ => warn!("Snapshot with bix = {} is skipped", bix),
+            }
+        }
+        Ok(None)
+    }
+
+    /// Remove snapshots after `bix` (used when the blockchain is truncated).
+    pub async fn discard_after(&self, bix: u64) -> TokioResult<()> {
+        for bix in self.list().await?.into_iter().filter(|b| *b > bix) {
+            tokio::fs::remove_file(self.get_path(bix)).await?;
+        }
+        Ok(())
+    }
+
+    fn get_path(&self, bix: u64) -> String {
+        path_concat!(self.path.clone(), format!("state-{}.json", bix))
+    }
+}
@@ -0,0 +1,110 @@
+use log::info;
+use serde::Deserialize;
+use uqoin_core::block::BlockInfo;
+
+use crate::utils::*;
+use crate::remote::request_node;
+use crate::peers::parse_node_url;
+use crate::scopes::blockchain::BlockQuery;
+
+
+#[derive(Deserialize)]
+struct VersionInfo {
+    version: String,
+}
+
+
+pub async fn task(appdata: WebAppData) -> TokioResult<()> {
+    // Random generator
+    let mut rng = rand::rng();
+
+    loop {
+        // Known nodes are not rate limited, their IPs are also resolved
+        // again periodically as the addresses may change
+        appdata.refresh_exempt().await;
+
+        // Discovery timeout (the task stops on shutdown)
+        if appdata.sleep_or_shutdown(appdata.config.node_discovery_timeout)
+                  .await {
+            return Ok(());
+        }
+
+        // Skip if there are enough nodes
+        if appdata.nodes.read().await.len() >= appdata.config.nodes_max {
+            continue;
+        }
+
+        // Choose a random node to ask for its nodes
+        let node = appdata.nodes.read().await.choose(&mut rng);
+
+        if let Some(node) = node {
+            info!("Discovering nodes of {}", node);
+
+            if let Ok(candidates) = request_node::<Vec<String>, _>(
+                    &node, "/node/list", None::<()>).await {
+                for candidate in candidates.iter() {
+                    // Skip invalid URLs
+                    let Some(candidate) = parse_node_url(candidate) else {
+                        continue;
+                    };
+
+                    // Skip known nodes and the node itself
+                    if is_known(&candidate, &appdata).await {
+                        continue;
+                    }
+
+                    // Probe the candidate and add it if it is compatible
+                    if probe_node(&candidate, &appdata).await {
+                        let mut nodes = appdata.nodes.write().await;
+                        if nodes.len() >= appdata.config.nodes_max {
+                            break;
+                        }
+                        nodes.push(candidate.clone());
+                        nodes.dump_list(&appdata.config.get_nodes_path())
+                             .await?;
+                        drop(nodes);
+                        appdata.refresh_exempt().await;
+
+                        info!("New node discovered: {}", candidate);
+                    }
+                }
+            } else {
+                info!("Cound not get nodes of {}", node);
+            }
+        }
+    }
+}
+
+
+async fn is_known(candidate: &str, appdata: &WebAppData) -> bool {
+    appdata.config.public_url.as_deref() == Some(candidate) ||
+    appdata.nodes.read().await.contains(candidate)
+}
+
+
+/// Check the node is reachable and its blockchain starts from the same block
+/// as the local one.
+pub async fn probe_node(node: &str, appdata: &WebAppData) -> bool {
+    // Check the node responds as Uqoin node
+    match request_node::<VersionInfo, _>(node, "/version", None::<()>).await {
+        Ok(info) => info!("Node {} has version {}", node, info.version),
+        Err(_) => return false,
+    }
+
+    // Get local first block (if any)
+    let hash_local = appdata.blockchain.read().await.get_block_info(1).await
+                            .ok().map(|block_info| block_info.hash);
+
+    if let Some(hash_local) = hash_local {
+        // Compare the first blocks
+        request_node::<BlockInfo, _>(
+            node, "/blockchain/block-info", Some(BlockQuery { bix: Some(1) })
+        ).await.map(|block_info| block_info.hash == hash_local)
+               .unwrap_or(false)
+    } else {
+        // Any reachable node is compatible with empty blockchain
+        request_node::<BlockInfo, _>(
+            node, "/blockchain/block-info", None::<BlockQuery>
+        ).await.is_ok()
+    }
+}
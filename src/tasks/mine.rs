@@ -1,118 +1,194 @@
 use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 
 use rand::Rng;
 use log::{info, warn};
-use tokio::time::{sleep, Duration};
+use tokio::time::Instant;
 use uqoin_core::utils::U256;
 use uqoin_core::block::{Block, COMPLEXITY};
 use uqoin_core::transaction::Transaction;
 
 use crate::utils::*;
+use crate::gossip::announce_block;
+use crate::chain::{dump_state, coin_moves};
+use crate::mining::Mining;
+use crate::events::{Envelope, coin_deltas};
 
 
-pub async fn task(appdata: WebAppData) -> TokioResult<()> {
-    // Input data for threads
-    let block_hash_arc: Arc<RwLock<Option<U256>>> = 
-        Arc::new(RwLock::new(None));
-    let transactions_arc: Arc<RwLock<Option<Vec<Transaction>>>> = 
-        Arc::new(RwLock::new(None));
-
-    // Threads output
-    let out_arc: Arc<RwLock<Option<(U256, Vec<Transaction>, [u8; 32])>>> = 
-        Arc::new(RwLock::new(None));
-
-    // Create threads
-    for _ in 0..appdata.config.mining_threads {
-        // Copy arcs
-        let block_hash_arc = Arc::clone(&block_hash_arc);
-        let transactions_arc = Arc::clone(&transactions_arc);
-        let out_arc = Arc::clone(&out_arc);
-        let public_key = appdata.config.public_key.clone().unwrap();
-        let mining_nonce_count_per_iteration = 
-            appdata.config.mining_nonce_count_per_iteration;
-
-        // Spawn a thread
-        std::thread::spawn(move || {
-            // Random generator
-            let mut rng = rand::rng();
-
-            // Infinite loop
-            loop {
-                // Clone intermediate params
-                let block_hash = block_hash_arc.read().unwrap().clone();
-                let transactions = transactions_arc.read().unwrap().clone();
-                let out = out_arc.read().unwrap().clone();
-
-                // If input is set and output is not set, run mining
-                if block_hash.is_some() && transactions.is_some() {
-                    if out.is_none() || (out.unwrap().1.len() 
-                                      < transactions.as_ref().unwrap().len()) {
-                        // Mine nonce
-                        let nonce = Block::mine(
-                            &mut rng, block_hash.as_ref().unwrap(), &public_key,
-                            transactions.as_ref().unwrap(), COMPLEXITY, 
-                            Some(mining_nonce_count_per_iteration)
-                        );
-
-                        // If nonce is mined, set `out`
-                        if let Some(nonce) = nonce {
-                            *out_arc.write().unwrap() = Some((
-                                block_hash.unwrap(), 
-                                transactions.unwrap(), 
-                                nonce,
-                            ));
-                        }
-
-                        // Continue the loop
-                        continue;
-                    }
-                }
+/// Mined block candidate: previous block hash, transactions and nonce.
+type MiningOutput = (U256, Vec<Transaction>, [u8; 32]);
 
-                // Wait for a while if params are not ready
-                std::thread::sleep(std::time::Duration::from_millis(1));
-            }
-        });
+
+/// Input and output of the mining threads.
+#[derive(Clone, Default)]
+struct ThreadData {
+    block_hash: Arc<RwLock<Option<U256>>>,
+    transactions: Arc<RwLock<Option<Vec<Transaction>>>>,
+    out: Arc<RwLock<Option<MiningOutput>>>,
+}
+
+
+/// Guard that keeps stop flags of the running mining threads. It stops the
+/// threads and marks mining as stopped when the task is finished or
+/// restarted.
+struct MiningGuard<'a> {
+    threads: Vec<Arc<AtomicBool>>,
+    mining: &'a Mining,
+}
+
+
+impl Drop for MiningGuard<'_> {
+    fn drop(&mut self) {
+        for stop in self.threads.iter() {
+            stop.store(true, Ordering::Relaxed);
+        }
+        self.mining.stop();
     }
+}
+
+
+pub async fn task(appdata: WebAppData) -> TokioResult<()> {
+    // Input and output data for threads
+    let data = ThreadData::default();
+
+    // Mark mining as active, the threads are created below
+    appdata.mining.start();
+    let mut guard = MiningGuard { threads: Vec::new(), mining: &appdata.mining };
 
     // Random generator
     let mut rng = rand::rng();
 
+    // Time to calculate hashrate
+    let mut instant = Instant::now();
+
     // Infinite loop to process pool, state and threads
     loop {
         // Try to update transactions to join `MINING_UPDATE_COUNT` times with  
         // the sleepage `MINING_TIMEOUT`.
         for _ in 0..appdata.config.mining_update_count {
-            // Get ready transactions for the next block
-            let (block_hash, transactions) = 
-                get_transactions_from_pool(&mut rng, &appdata).await;
-
-            // Update mining params if block hash or transactions changed
-            if (*block_hash_arc.read().unwrap() != Some(block_hash.clone())) 
-                    || (transactions_arc.read().unwrap().as_ref()
-                        .unwrap_or(&vec![]).len() < transactions.len()) {
-                *block_hash_arc.write().unwrap() = Some(block_hash.clone());
-                *transactions_arc.write().unwrap() = Some(transactions.clone());
+            // Start or stop threads according to the requested thread count
+            adjust_threads(&mut guard, &data, &appdata);
+
+            if appdata.mining.is_paused() {
+                // Nothing is mined while paused
+                *data.block_hash.write().unwrap() = None;
+                *data.transactions.write().unwrap() = None;
+                appdata.mining.clear_target();
+            } else {
+                // Get ready transactions for the next block
+                let (block_hash, transactions) = 
+                    get_transactions_from_pool(&mut rng, &appdata).await;
+
+                // Update mining params if block hash or transactions changed
+                if (*data.block_hash.read().unwrap() != 
+                        Some(block_hash.clone())) || 
+                        (data.transactions.read().unwrap().as_ref()
+                            .unwrap_or(&vec![]).len() < transactions.len()) {
+                    *data.block_hash.write().unwrap() = 
+                        Some(block_hash.clone());
+                    *data.transactions.write().unwrap() = 
+                        Some(transactions.clone());
+                    appdata.mining.set_target(block_hash, transactions.len());
+                }
             }
 
-            // Sleep
-            sleep(Duration::from_millis(
+            // Sleep (the task stops on shutdown)
+            if appdata.sleep_or_shutdown(
                 appdata.config.get_mining_validate_iter_timeout()
-            )).await;
+            ).await {
+                return Ok(());
+            }
+
+            // Update hashrate
+            appdata.mining.update_hashrates(instant.elapsed().as_secs_f64());
+            instant = Instant::now();
         }
 
-        // Check if nonce is mined
-        let mut out = out_arc.write().unwrap();
-        if let Some((block_hash, transactions, nonce)) = out.as_ref() {
+        // Check if nonce is mined (taking it sets the output back to `None`)
+        let out = data.out.write().unwrap().take();
+        if let Some((block_hash, transactions, nonce)) = out {
             // Add new block
-            add_new_block(block_hash, transactions, nonce, &appdata).await?;
+            add_new_block(&block_hash, &transactions, &nonce, &appdata).await?;
+        }
+    }
+}
 
-            // Set back to `None`
-            *out = None;
+
+/// Spawn or stop mining threads so their number matches the requested one
+/// (zero if mining is paused). Stopped threads exit after their current
+/// iteration.
+fn adjust_threads(guard: &mut MiningGuard, data: &ThreadData, 
+                  appdata: &WebAppData) {
+    let thread_count = appdata.mining.get_running_thread_count();
+
+    while guard.threads.len() < thread_count {
+        let counter = appdata.mining.add_thread();
+        guard.threads.push(spawn_thread(data.clone(), counter, appdata));
+    }
+
+    while guard.threads.len() > thread_count {
+        if let Some(stop) = guard.threads.pop() {
+            stop.store(true, Ordering::Relaxed);
+            appdata.mining.remove_thread();
         }
     }
 }
 
 
+/// Spawn a mining thread counting the tried nonces in `counter`. It returns
+/// the flag to stop the thread.
+fn spawn_thread(data: ThreadData, counter: Arc<AtomicU64>, 
+                appdata: &WebAppData) -> Arc<AtomicBool> {
+    let stop = Arc::new(AtomicBool::new(false));
+    let stop_thread = Arc::clone(&stop);
+    let public_key = appdata.config.public_key.clone().unwrap();
+    let mining_nonce_count_per_iteration = 
+        appdata.config.mining_nonce_count_per_iteration;
+
+    std::thread::spawn(move || {
+        // Random generator
+        let mut rng = rand::rng();
+
+        // Loop until the stop flag is set
+        while !stop_thread.load(Ordering::Relaxed) {
+            // Clone intermediate params
+            let block_hash = data.block_hash.read().unwrap().clone();
+            let transactions = data.transactions.read().unwrap().clone();
+            let out = data.out.read().unwrap().clone();
+
+            // If input is set and output is not set, run mining
+            if let (Some(block_hash), Some(transactions)) = 
+                    (block_hash, transactions) && out.is_none_or(
+                        |out| out.1.len() < transactions.len()
+                    ) {
+                // Mine nonce
+                let nonce = Block::mine(
+                    &mut rng, &block_hash, &public_key, &transactions, 
+                    COMPLEXITY, Some(mining_nonce_count_per_iteration)
+                );
+                counter.fetch_add(mining_nonce_count_per_iteration as u64, 
+                                  Ordering::Relaxed);
+
+                // If nonce is mined, set `out`
+                if let Some(nonce) = nonce {
+                    *data.out.write().unwrap() = Some((
+                        block_hash, transactions, nonce,
+                    ));
+                }
+
+                // Continue the loop
+                continue;
+            }
+
+            // Wait for a while if params are not ready
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+    });
+
+    stop
+}
+
+
 async fn get_transactions_from_pool<R: Rng>(
         rng: &mut R, appdata: &WebAppData) -> (U256, Vec<Transaction>) {
     // Get state and pool
@@ -124,7 +200,7 @@ async fn get_transactions_from_pool<R: Rng>(
     // the performance.
     let (transactions, _) = pool.prepare(
         rng, &state, &appdata.schema, 
-        &appdata.config.private_key.as_ref().unwrap(), 
+        appdata.config.private_key.as_ref().unwrap(), 
         appdata.config.mining_groups_max
     );
 
@@ -149,7 +225,7 @@ async fn add_new_block(block_hash: &U256, transactions: &[Transaction],
     // If block hash is relevant
     if block_hash == &last_block_info.hash {
         // Calculate senders
-        let senders = Transaction::calc_senders(&transactions, &state, 
+        let senders = Transaction::calc_senders(transactions, &state, 
                                                 &appdata.schema);
 
         // Create a new block
@@ -163,18 +239,40 @@ async fn add_new_block(block_hash: &U256, transactions: &[Transaction],
                 // Push new block
                 let bix = blockchain.push_new_block(&block, transactions).await?;
 
+                // Index coin moves of the wallets
+                let moves = coin_moves(&block, transactions, &senders, &state);
+                appdata.history.write().await.push(bix, &block.hash, &moves)
+                    .await?;
+
                 // Change state
                 state.roll_up(bix, &block, transactions, &appdata.schema);
+                appdata.events.emit(Envelope::block_added(
+                    bix, &block, transactions, &senders
+                ));
+                if appdata.events.is_active() {
+                    for envelope in coin_deltas(bix, &moves, false) {
+                        appdata.events.emit(envelope);
+                    }
+                }
 
                 // Update pool
                 let mut pool = appdata.pool.write().await;
+                pool.confirm(bix, &block, transactions);
                 pool.update(&state, &appdata.schema);
 
                 // Dump state
-                state.dump(&appdata.config.get_state_path()).await?;
+                dump_state(&appdata.config.get_state_path(), &state)
+                    .await?;
+                appdata.snapshots.save(&state).await?;
+
+                // Count the mined block
+                appdata.mining.block_mined();
 
                 // Log
                 info!("New block added, bix = {}", bix);
+
+                // Announce the block to the other nodes
+                announce_block(appdata, state.get_last_block_info());
             },
             Err(err) => {
                 warn!("Unable to build a block: {:?}", err);
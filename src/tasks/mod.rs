@@ -1,5 +1,9 @@
 pub mod sync;
 pub mod mine;
+pub mod discover;
+pub mod persist;
 
 pub use sync::{task as sync_task};
 pub use mine::{task as mine_task};
+pub use discover::{task as discover_task};
+pub use persist::{task as persist_task};
@@ -0,0 +1,25 @@
+use log::info;
+
+use crate::utils::*;
+
+
+/// Periodically drop stale groups from the pool and save it to the file.
+pub async fn task(appdata: WebAppData) -> TokioResult<()> {
+    loop {
+        // Dump timeout (the task stops on shutdown)
+        if appdata.sleep_or_shutdown(appdata.config.pool_dump_timeout).await {
+            return Ok(());
+        }
+
+        // Drop stale groups
+        let mut pool = appdata.pool.write().await;
+        let count = pool.len();
+        pool.prune(appdata.config.pool_group_age_max);
+        if pool.len() < count {
+            info!("Dropped {} stale groups from pool", count - pool.len());
+        }
+
+        // Save pool
+        pool.dump(&appdata.config.get_pool_path()).await?;
+    }
+}
@@ -1,17 +1,19 @@
 use log::{info, warn, error};
-use rand::prelude::IndexedRandom;
 use tokio::io::{Error, ErrorKind};
-use tokio::time::{sleep, Duration};
-use serde::Serialize;
-use serde::de::DeserializeOwned;
+use tokio::time::{sleep, Duration, Instant};
 use uqoin_core::utils::U256;
 use uqoin_core::block::{BlockInfo, BlockData, COMPLEXITY};
 use uqoin_core::blockchain::Blockchain;
 use uqoin_core::state::State;
 use uqoin_core::transaction::{Transaction, Group, group_transactions};
 
-use crate::async_try_many;
 use crate::utils::*;
+use crate::remote::{request_node, request_node_retry};
+use crate::gossip::announce_block;
+use crate::events::{Event, Envelope, coin_deltas};
+use crate::chain::{CoinMove, replay_until, dump_state, coin_moves, 
+                   collect_groups};
+use crate::history::History;
 use crate::scopes::blockchain::{BlockQuery, BlockManyQuery};
 
 
@@ -23,156 +25,228 @@ pub async fn task(appdata: WebAppData) -> TokioResult<()> {
     let mut rng = rand::rng();
 
     loop {
-        // Sync timeout
-        sleep(Duration::from_millis(appdata.config.node_sync_timeout)).await;
-
-        // Choose a random node
-        if let Some(random_node) = appdata.nodes.read().await.choose(&mut rng) {
-            info!("Trying to sync with {}", random_node);
-
-            // Request last block info of the node
-            if let Ok(last_info_remote) = request_node::<BlockInfo, _>(
-                    &random_node, "/blockchain/block-info", 
-                    None::<BlockQuery>).await {
-                // Get local last block info
-                let last_info_local: BlockInfo = appdata.state.read().await
-                                                .get_last_block_info().clone();
-
-                // Sync basic condition
-                let neet_to_sync = 
-                    last_info_remote.offset > last_info_local.offset ||
-                    (
-                        last_info_remote.offset == last_info_local.offset && 
-                        last_info_remote.bix < last_info_local.bix
-                    );
-                if neet_to_sync {
-                    info!("Need to sync with {}", random_node);
+        // Sync timeout (it is interrupted if a node announces a new block,
+        // the task stops on shutdown)
+        tokio::select! {
+            _ = sleep(Duration::from_millis(appdata.config.node_sync_timeout)) 
+                => {},
+            _ = appdata.sync_notify.notified() => {},
+            _ = appdata.shutdown_signalled() => return Ok(()),
+        }
 
-                    // Request for sync point
-                    let bix_sync = request_for_divergent_bix(
-                        std::cmp::min(last_info_remote.bix, last_info_local.bix),
-                        &random_node, &*appdata.blockchain.read().await
-                    ).await?;
+        // Take the announcing node or choose a random one
+        let node = if let Some(node) = appdata.sync_target.write().await
+                                              .take() {
+            Some(node)
+        } else {
+            appdata.nodes.read().await.choose(&mut rng)
+        };
+
+        if let Some(node) = node {
+            // Operator actions replacing blocks wait for the round to finish
+            let _sync_guard = appdata.sync_lock.lock().await;
+
+            appdata.metrics.sync_started(&node);
+            match sync_with_node(&node, &appdata).await {
+                Ok(success) => appdata.metrics.sync_finished(&node, success),
+                Err(_) if appdata.is_shutting_down() => return Ok(()),
+                Err(err) => {
+                    // Count an error as node failure and restart the task
+                    appdata.metrics.sync_finished(&node, false);
+                    appdata.nodes.write().await.report_failure(&node);
+                    return Err(err);
+                },
+            }
+        }
+    }
+}
 
-                    info!("Need to sync after bix = {}", bix_sync);
 
-                    // Limit the block count to sync
-                    let bix_until = std::cmp::min(
-                        last_info_remote.bix, 
-                        bix_sync + appdata.config.node_sync_block_count
-                    );
+/// Sync with the node if it has a better blockchain. It returns `false` if
+/// the node is unreachable or serves invalid blocks.
+async fn sync_with_node(node: &str, appdata: &WebAppData) -> 
+                        TokioResult<bool> {
+    info!("Trying to sync with {}", node);
 
-                    // Set syncing if there are too many blocks forward to sync
-                    if bix_until < last_info_remote.bix {
-                        set_syncing_status(&appdata, true).await;
-                    }
+    // Request last block info of the node
+    let instant = Instant::now();
+    if let Ok(last_info_remote) = request_node::<BlockInfo, _>(
+            node, "/blockchain/block-info", None::<BlockQuery>).await {
+        // Register the response latency
+        appdata.nodes.write().await.report_success(
+            node, instant.elapsed().as_millis() as u64
+        );
+
+        // Get local last block info
+        let last_info_local: BlockInfo = appdata.state.read().await
+                                        .get_last_block_info().clone();
+
+        // Sync basic condition
+        if need_to_sync(&last_info_remote, &last_info_local) {
+            info!("Need to sync with {}", node);
+
+            // Request for sync point
+            let bix_sync = request_for_divergent_bix(
+                std::cmp::min(last_info_remote.bix, last_info_local.bix),
+                node, appdata, &*appdata.blockchain.read().await
+            ).await?;
+
+            info!("Need to sync after bix = {}", bix_sync);
+
+            // Limit the block count to sync
+            let bix_until = std::cmp::min(
+                last_info_remote.bix, 
+                bix_sync + appdata.config.node_sync_block_count
+            );
+
+            // Set syncing if there are too many blocks forward to sync
+            if bix_until < last_info_remote.bix {
+                set_syncing_status(appdata, true).await;
+            }
 
-                    // Request for remote blocks
-                    let blocks = request_for_remote_blocks(
-                        bix_sync + 1, bix_until, &random_node
-                    ).await?;
-
-                    info!("Got {} blocks to roll up", blocks.len());
-
-                    // Check divergent blocks
-                    if let Some((state_new, trs_vec)) = 
-                            check_divergent_blocks(&blocks, &appdata).await? {
-                        info!("Syncing with {}", random_node);
-
-                        // Lock blockchain, state and pool
-                        let blockchain = appdata.blockchain.write().await;
-                        let mut state = appdata.state.write().await;
-                        let mut pool = appdata.pool.write().await;
-
-                        // Migrate blockchain
-                        migrate_blockchain(&blocks, &blockchain).await?;
-
-                        // Update state
-                        *state = state_new;
-
-                        // Update pool
-                        for trs in trs_vec.into_iter() {
-                            let senders = Transaction::calc_senders(
-                                &trs, &state, &appdata.schema
-                            );
-                            if let Ok(group) = Group::new(trs, &state, 
-                                                          &senders) {
-                                pool.add(group, senders[0].clone());
-                            }
-                        }
-                        pool.update(&state, &appdata.schema);
-
-                        // Dump state
-                        state.dump(&appdata.config.get_state_path()).await?;
-
-                        // Unset is_syncing if everything is up to date
-                        if bix_until == last_info_remote.bix {
-                            set_syncing_status(&appdata, false).await;
-                        }
-
-                        info!("Synced with {} successfully", random_node);
-                    } else {
-                        // Unset is_syncing if block is invalid
-                        set_syncing_status(&appdata, false).await;
-
-                        info!("Blocks are invalid in {}", random_node);
+            // Request for remote blocks
+            let blocks = request_for_remote_blocks(
+                bix_sync + 1, bix_until, node, appdata
+            ).await?;
+
+            info!("Got {} blocks to roll up", blocks.len());
+
+            // Check divergent blocks
+            let check = check_divergent_blocks(&blocks, appdata).await?;
+            if let Check::Valid(rebase) = check {
+                info!("Syncing with {}", node);
+
+                // Lock blockchain, state and pool
+                let blockchain = appdata.blockchain.write().await;
+                let mut state = appdata.state.write().await;
+                let mut pool = appdata.pool.write().await;
+                let mut history = appdata.history.write().await;
+
+                // Retry if a block is added or removed since the check
+                let tip = state.get_last_block_info();
+                if tip.bix != rebase.tip.bix || tip.hash != rebase.tip.hash || 
+                        blockchain.get_block_count().await? != rebase.tip.bix {
+                    drop((blockchain, state, pool, history));
+                    return retry_round(node, appdata).await;
+                }
+
+                // Migrate blockchain and wallet history
+                migrate_blockchain(&blocks, &rebase.moves, &blockchain, 
+                                   &mut history).await?;
+                appdata.snapshots.discard_after(bix_sync).await?;
+
+                // Update state
+                *state = rebase.state;
+
+                // Emit events of the new blocks
+                if rebase.tip.bix > bix_sync {
+                    let depth = rebase.tip.bix - bix_sync;
+                    appdata.metrics.reorg(depth);
+                    appdata.events.emit(Envelope::global(Event::Reorg {
+                        old_tip: rebase.tip.clone(),
+                        new_tip: state.get_last_block_info().clone(),
+                        depth,
+                    }));
+                }
+                for envelope in rebase.events.into_iter() {
+                    appdata.events.emit(envelope);
+                }
+
+                // Update pool
+                pool.revert(bix_sync);
+                for trs in rebase.orphans.into_iter() {
+                    let senders = Transaction::calc_senders(
+                        &trs, &state, &appdata.schema
+                    );
+                    if let Ok(group) = Group::new(trs, &state, 
+                                                  &senders) {
+                        let _ = pool.add(group, senders[0].clone(), &state);
                     }
-                } else {
-                    // Unset is_syncing if nothing to sync
-                    set_syncing_status(&appdata, false).await;
+                }
+                for block_data in blocks.iter() {
+                    pool.confirm(block_data.bix, &block_data.block, 
+                                 &block_data.transactions);
+                }
+                pool.update(&state, &appdata.schema);
 
-                    info!("No need to sync with {}", random_node);
+                // Dump state
+                dump_state(&appdata.config.get_state_path(), &state).await?;
+                appdata.snapshots.save(&state).await?;
+
+                // Unset is_syncing if everything is up to date
+                if bix_until == last_info_remote.bix {
+                    set_syncing_status(appdata, false).await;
                 }
+
+                info!("Synced with {} successfully", node);
+
+                // Announce the new last block to the other nodes
+                announce_block(appdata, state.get_last_block_info());
+            } else if let Check::Changed = check {
+                return retry_round(node, appdata).await;
             } else {
-                info!("Cound not reach the node {}", random_node);
+                // Unset is_syncing if block is invalid
+                set_syncing_status(appdata, false).await;
+
+                // Register invalid blocks from the node
+                appdata.nodes.write().await.report_invalid(node);
+
+                info!("Blocks are invalid in {}", node);
+                return Ok(false);
             }
+        } else {
+            // Unset is_syncing if nothing to sync
+            set_syncing_status(appdata, false).await;
+
+            info!("No need to sync with {}", node);
         }
+    } else {
+        appdata.nodes.write().await.report_failure(node);
+        info!("Cound not reach the node {}", node);
+        return Ok(false);
     }
+    Ok(true)
 }
 
 
-async fn set_syncing_status(appdata: &WebAppData, value: bool) {
-    if *appdata.is_syncing.read().await != value {
-        *appdata.is_syncing.write().await = value;
-    }
+/// Check if the remote blockchain is better than the local one, so it is
+/// necessary to sync with it.
+pub fn need_to_sync(last_info_remote: &BlockInfo, 
+                    last_info_local: &BlockInfo) -> bool {
+    last_info_remote.offset > last_info_local.offset ||
+    (
+        last_info_remote.offset == last_info_local.offset && 
+        last_info_remote.bix < last_info_local.bix
+    )
 }
 
 
-async fn request_node<T: DeserializeOwned, Q: Serialize>(
-        node: &str, path: &str, qs: Option<Q>) -> TokioResult<T> {
-    let query = qs.map(|q| serde_qs::to_string(&q).unwrap());
-
-    let url = if let Some(query) = query {
-        format!("{}{}?{}", node, path, query)
-    } else {
-        format!("{}{}", node, path)
-    };
-
-    info!("External node request: {}", url);
-
-    let client = reqwest::Client::builder()
-        .timeout(std::time::Duration::from_secs(5)).build().unwrap();
+/// Sync with the node again since the local blockchain has changed during the
+/// round.
+async fn retry_round(node: &str, appdata: &WebAppData) -> TokioResult<bool> {
+    info!("Local blockchain changed while syncing with {}, retrying", node);
+    appdata.request_sync(node.to_string()).await;
+    Ok(true)
+}
 
-    let resp = client.get(&url).send().await
-                     .map_err(|_| Error::new(ErrorKind::NotFound, url))?;
 
-    let content: String = resp.text().await
-        .map_err(|err| Error::new(ErrorKind::InvalidData, err))?;
-    let instance = serde_json::from_str::<T>(&content)?;
-    Ok(instance)
+async fn set_syncing_status(appdata: &WebAppData, value: bool) {
+    // The value forced by the operator takes priority
+    let value = appdata.syncing_override.read().await.unwrap_or(value);
+    appdata.set_syncing(value).await;
 }
 
 
 async fn request_for_divergent_bix(bix_last: u64, node: &str, 
+                                   appdata: &WebAppData, 
                                    blockchain: &Blockchain) -> 
                                    TokioResult<u64> {
     find_divergence(bix_last, async |bix| {
         // Get remote block info
-        let block_info: BlockInfo = async_try_many!(
-            TRY_NODE_ATTEMPTS, request_node, 
-            node, "/blockchain/block-info",
+        let block_info: BlockInfo = request_node_retry(
+            appdata, TRY_NODE_ATTEMPTS, node, "/blockchain/block-info",
             Some(BlockQuery { bix: Some(bix) })
-        )?;
+        ).await?;
 
         // Get local block hash for the `bix`
         let hash_local = blockchain.get_block_info(bix).await?.hash;
@@ -185,26 +259,82 @@ async fn request_for_divergent_bix(bix_last: u64, node: &str,
 }
 
 
-async fn request_for_remote_blocks(bix_from: u64, bix_to: u64, node: &str) -> 
+async fn request_for_remote_blocks(bix_from: u64, bix_to: u64, node: &str, 
+                                   appdata: &WebAppData) -> 
                                    TokioResult<Vec<BlockData>> {
-    async_try_many!(
-        TRY_NODE_ATTEMPTS, request_node, 
-        &node, "/blockchain/block-many",
+    request_node_retry(
+        appdata, TRY_NODE_ATTEMPTS, node, "/blockchain/block-many",
         Some(BlockManyQuery { bix: bix_from, count: bix_to + 1 - bix_from })
-    )
+    ).await
+}
+
+
+/// Result of checking the remote blocks against the local blockchain.
+enum Check {
+    /// The remote blocks are valid and can be applied.
+    Valid(Box<Rebase>),
+
+    /// The remote blocks are invalid.
+    Invalid,
+
+    /// The local blockchain is shorter than the sync point, since it has
+    /// changed after the point was found.
+    Changed,
+}
+
+
+/// State and pool changes after the remote blocks are applied.
+struct Rebase {
+    /// Local last block the changes are calculated for.
+    tip: BlockInfo,
+
+    /// State at the last remote block.
+    state: State,
+
+    /// Transaction groups of the rolled down local blocks.
+    orphans: Vec<Vec<Transaction>>,
+
+    /// Coin moves of the remote blocks to index.
+    moves: Vec<Vec<CoinMove>>,
+
+    /// Events of the remote blocks to emit after the migration.
+    events: Vec<Envelope>,
 }
 
 
 async fn check_divergent_blocks(blocks: &[BlockData], appdata: &WebAppData) -> 
-                                TokioResult<Option<(State, Vec<Vec<Transaction>>)>> {
+                                TokioResult<Check> {
     // Get blockchain and clone the current state and pool
     let blockchain = appdata.blockchain.read().await;
     let mut state = appdata.state.read().await.clone();
     let mut trs_vec = Vec::new();
+    let mut events = Vec::new();
+    let mut moves_vec = Vec::new();
+    let is_active = appdata.events.is_active();
 
     let bix_sync = blocks[0].bix - 1;
 
+    let tip = state.get_last_block_info().clone();
     let mut bix = blockchain.get_block_count().await?;
+    let Some(depth) = bix.checked_sub(bix_sync) else {
+        return Ok(Check::Changed);
+    };
+
+    // Restore the nearest snapshot for deep reorgs if it is closer than the
+    // divergence point to the tip
+    let interval = appdata.snapshots.interval();
+    if interval > 0 && depth > interval && 
+            let Some(snapshot) = appdata.snapshots.load_nearest(
+                bix_sync, &blockchain).await? && 
+            bix_sync - snapshot.get_last_block_info().bix < depth {
+        warn!("Restoring snapshot with bix = {} to roll back {} blocks", 
+              snapshot.get_last_block_info().bix, depth);
+        trs_vec = collect_groups(&blockchain, bix_sync + 1, bix).await?;
+        state = snapshot;
+        replay_until(&mut state, &blockchain, &appdata.schema, bix_sync, 
+                     false).await?;
+        bix = bix_sync;
+    }
 
     // Roll down the state and pool with local blocks
     while bix > bix_sync {
@@ -231,6 +361,13 @@ async fn check_divergent_blocks(blocks: &[BlockData], appdata: &WebAppData) ->
         let senders = Transaction::calc_senders(&block_data.transactions, 
                                                 &state, &appdata.schema);
 
+        // Coin changes of the wallets (for the subscribers only)
+        if is_active {
+            let moves = coin_moves(&block_data.block, &block_data.transactions,
+                                   &senders, &state);
+            events.extend(coin_deltas(bix, &moves, true));
+        }
+
         // Collect rolled down groups of transactions
         for (_, group, _) in group_transactions(block_data.transactions, &state, 
                                                 &senders) {
@@ -262,28 +399,44 @@ async fn check_divergent_blocks(blocks: &[BlockData], appdata: &WebAppData) ->
             break;
         }
 
+        // Coin moves of the wallets to index
+        let moves = coin_moves(&block_data.block, &block_data.transactions,
+                               &senders, &state);
+
         // Roll up state
         state.roll_up(block_data.bix, &block_data.block, 
                       &block_data.transactions, &appdata.schema);
+        events.push(Envelope::block_added(block_data.bix, &block_data.block,
+                                          &block_data.transactions, 
+                                          &senders));
+        if is_active {
+            events.extend(coin_deltas(block_data.bix, &moves, false));
+        }
+        moves_vec.push(moves);
 
         // Change previous block info
         block_info_prev = block_data.get_block_info();
     }
 
     if is_valid {
-        Ok(Some((state, trs_vec)))
+        Ok(Check::Valid(Box::new(Rebase { 
+            tip, state, orphans: trs_vec, moves: moves_vec, events 
+        })))
     } else {
-        Ok(None)
+        Ok(Check::Invalid)
     }
 }
 
 
-async fn migrate_blockchain(blocks: &[BlockData], 
-                            blockchain: &Blockchain) -> TokioResult<()> {
+async fn migrate_blockchain(blocks: &[BlockData], moves: &[Vec<CoinMove>],
+                            blockchain: &Blockchain, 
+                            history: &mut History) -> TokioResult<()> {
     blockchain.truncate(blocks[0].bix - 1).await?;
-    for block_data in blocks.iter() {
+    history.truncate(blocks[0].bix - 1, blockchain).await?;
+    for (block_data, moves) in blocks.iter().zip(moves.iter()) {
         blockchain.push_new_block(&block_data.block, 
                                   &block_data.transactions).await?;
+        history.push(block_data.bix, &block_data.block.hash, moves).await?;
     }
     Ok(())
 }
@@ -1,7 +1,8 @@
 use tokio::io::Result as TkResult;
 use actix_web::{web, HttpResponse, Result as ActixResult};
+use uqoin_core::utils::U256;
 
-use crate::error::JsonError;
+use crate::error::{JsonError, ErrorCode};
 use crate::appdata::AppData;
 
 
@@ -10,6 +11,44 @@ pub type APIResult = ActixResult<HttpResponse, JsonError>;
 pub type WebAppData = web::Data<AppData>;
 
 
+/// Current time in milliseconds since epoch.
+pub fn timestamp() -> u64 {
+    std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH)
+        .unwrap().as_millis() as u64
+}
+
+
+/// Parse 256-bit number from exactly 64 hex digits.
+pub fn parse_u256(hex: &str) -> Option<U256> {
+    let is_valid = hex.len() == 64 && 
+                   hex.chars().all(|c| c.is_ascii_hexdigit());
+    is_valid.then(|| U256::from_hex(hex))
+}
+
+
+/// Parse the request parameter `name` as 64 hex digits (`BadRequest` if it is
+/// malformed).
+pub fn parse_param(name: &str, hex: &str) -> Result<U256, JsonError> {
+    parse_u256(hex).ok_or(JsonError::with_detail(
+        ErrorCode::BadRequest, 
+        &format!("Invalid `{}`: 64 hex digits expected", name)
+    ))
+}
+
+
+/// Write the file atomically: the content is written to a temporary file
+/// that replaces the target one after it is synced to the disk, so the target
+/// file is never left partially written.
+pub async fn write_atomic(path: &str, content: &[u8]) -> TkResult<()> {
+    use tokio::io::AsyncWriteExt;
+    let path_tmp = format!("{}.tmp", path);
+    let mut file = tokio::fs::File::create(&path_tmp).await?;
+    file.write_all(content).await?;
+    file.sync_all().await?;
+    tokio::fs::rename(&path_tmp, path).await
+}
+
+
 /// This function searchs for `ix` such that `check(ix) == true` and 
 /// `check(ix+1) == false`. `check` must satisty `check(ix1) >= check(ix2)` for
 /// `ix1 < ix2`. `ix` should be from `0` to `ix_last` inclusively. The